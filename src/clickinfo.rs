@@ -0,0 +1,35 @@
+use crate::Point;
+
+/// Mouse button of a click
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+/// Kind of a click, see `TrayIconBuilder::events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickKind {
+    Left,
+    Right,
+    Double,
+}
+
+/// Modifier keys held down during a click
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+}
+
+/// Details of a click, see `TrayIconBuilder::on_click_info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClickInfo {
+    pub button: MouseButton,
+    pub double: bool,
+    pub modifiers: Modifiers,
+
+    /// Screen coordinates of the click
+    pub position: Point,
+}
@@ -1,9 +1,29 @@
 use crate::{Error, IconBase};
 use std::fmt::Debug;
 
+/// Standard system icons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StockIcon {
+    Application,
+    Information,
+    Warning,
+    Error,
+    Question,
+    Shield,
+}
+
+/// Where the icon came from, icons are compared by this
+#[derive(Clone, PartialEq)]
+enum IconSource {
+    Buffer(Vec<u8>, Option<u32>, Option<u32>),
+    Sizes(Vec<(u32, Vec<u8>)>),
+    Stock(StockIcon),
+}
+
 #[derive(Clone)]
 pub struct Icon {
-    buffer: Option<&'static [u8]>,
+    source: IconSource,
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
     pub(crate) sys: crate::IconSys,
 }
 
@@ -14,20 +34,108 @@ impl Debug for Icon {
 }
 
 impl Icon {
-    pub fn from_buffer(
-        buffer: &'static [u8],
+    /// Create icon from a buffer of an `.ico` file
+    ///
+    /// Whole `.ico` files work as is, e.g. `include_bytes!("app.ico")`. A
+    /// single icon image without the directory, PNG or bitmap as in the icon
+    /// resources, is accepted too.
+    ///
+    /// Accepts e.g. `include_bytes!`, arrays, slices and vectors. The buffer is
+    /// copied for comparing the icons, the OS icon has its own copy of the
+    /// bits.
+    ///
+    /// Without width and height the size is the small icon size at the DPI of
+    /// the taskbar's monitor, the closest image of the file is used. Each of
+    /// them defaults separately, e.g. only the width can be given.
+    pub fn from_buffer<B>(buffer: B, width: Option<u32>, height: Option<u32>) -> Result<Icon, Error>
+    where
+        B: AsRef<[u8]>,
+    {
+        Icon::from_owned(buffer.as_ref().to_vec(), width, height)
+    }
+
+    /// Create icon from an owned buffer
+    ///
+    /// Like `from_buffer`, but the buffer is kept without copying it.
+    pub fn from_owned(
+        buffer: Vec<u8>,
         width: Option<u32>,
         height: Option<u32>,
     ) -> Result<Icon, Error> {
+        let sys = crate::IconSys::from_buffer(&buffer, width, height)?;
+        Ok(Icon {
+            source: IconSource::Buffer(buffer, width, height),
+            sys,
+        })
+    }
+
+    /// Create icon from images of different sizes, e.g. `icon16.png` and
+    /// `icon32.png`
+    ///
+    /// Images are paired with their size in pixels, and accept the same
+    /// formats as `from_buffer`. The image closest to the tray icon size at the
+    /// current DPI is used, a larger image is preferred and scaled down. The
+    /// image is chosen again when the DPI changes.
+    pub fn from_sizes<I, B>(images: I) -> Result<Icon, Error>
+    where
+        I: IntoIterator<Item = (u32, B)>,
+        B: AsRef<[u8]>,
+    {
+        let images: Vec<(u32, Vec<u8>)> = images
+            .into_iter()
+            .map(|(size, buffer)| (size, buffer.as_ref().to_vec()))
+            .collect();
+        Ok(Icon {
+            sys: crate::IconSys::from_sizes(&images)?,
+            source: IconSource::Sizes(images),
+        })
+    }
+
+    /// Create icon from a standard system icon
+    ///
+    /// Icon matches the style of the current Windows version, older versions
+    /// without the stock icon API get the classic system icons.
+    pub fn from_stock(stock: StockIcon) -> Result<Icon, Error> {
         Ok(Icon {
-            buffer: Some(buffer),
-            sys: crate::IconSys::from_buffer(buffer, width, height)?,
+            source: IconSource::Stock(stock),
+            sys: crate::IconSys::from_stock(stock)?,
         })
     }
+
+    /// Is the icon loaded from the buffer with the size
+    pub(crate) fn is_from_buffer(
+        &self,
+        buffer: &[u8],
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> bool {
+        match &self.source {
+            IconSource::Buffer(b, w, h) => b == buffer && *w == width && *h == height,
+            _ => false,
+        }
+    }
+
+    /// Load the icon again for the current DPI
+    ///
+    /// `None` if the size of the icon doesn't depend on the DPI.
+    pub(crate) fn reload(&self) -> Option<Result<Icon, Error>> {
+        match &self.source {
+            IconSource::Buffer(buffer, width, height) if width.is_none() || height.is_none() => {
+                Some(Icon::from_buffer(buffer, *width, *height))
+            }
+            IconSource::Sizes(images) => Some(Icon::from_sizes(images.iter().cloned())),
+            _ => None,
+        }
+    }
+
+    /// Is the icon the stock icon
+    pub(crate) fn is_from_stock(&self, stock: StockIcon) -> bool {
+        self.source == IconSource::Stock(stock)
+    }
 }
 
 impl PartialEq for Icon {
     fn eq(&self, other: &Self) -> bool {
-        self.buffer == other.buffer
+        self.source == other.source
     }
 }
@@ -1,24 +1,54 @@
 //! ## Example
 //! [Open full example with winit here 🢅](https://github.com/Ciantic/trayicon-rs/blob/master/examples/winit/src/main.rs)
 
+// Library is used in GUI applications without console, never print anything
+#![cfg_attr(not(test), deny(clippy::print_stdout, clippy::print_stderr))]
+
 #[cfg(target_os = "windows")]
 #[path = "./sys/windows/mod.rs"]
 mod sys;
 
+#[cfg(not(target_os = "windows"))]
+#[path = "./sys/unsupported/mod.rs"]
+mod sys;
+
+mod clickinfo;
+mod geometry;
 mod icon;
 mod menubuilder;
+mod menuinit;
+mod notification;
+pub mod prelude;
+mod statustray;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod trayeventhandler;
 mod trayicon;
 mod trayiconbuilder;
+mod trayicondetached;
 mod trayiconsender;
+mod trayiconupdate;
 
 // Public api
-pub use crate::icon::Icon;
-pub use crate::menubuilder::{MenuBuilder, MenuItem};
+pub use crate::clickinfo::{ClickInfo, ClickKind, Modifiers, MouseButton};
+pub use crate::geometry::{Point, Rect};
+pub use crate::icon::{Icon, StockIcon};
+pub use crate::menubuilder::{MenuBuilder, MenuId, MenuItem};
+pub use crate::menuinit::MenuInit;
+pub use crate::notification::{NotificationIcon, NotificationOptions};
+pub use crate::statustray::{Status, StatusTray};
+#[cfg(any(test, feature = "testing"))]
+pub use crate::testing::SimulatedInput;
+pub use crate::trayeventhandler::TrayEventHandler;
 pub use crate::trayicon::TrayIcon;
 pub use crate::trayiconbuilder::Error;
+pub use crate::trayiconbuilder::NotifyVersion;
 pub use crate::trayiconbuilder::TrayIconBuilder;
+pub use crate::trayicondetached::DetachedTrayIcon;
+pub use crate::trayiconupdate::TrayIconUpdate;
 
-// Each OS specific implementation must export following:
+// Each OS specific implementation must export following, and additionally the
+// `is_high_contrast() -> bool` function:
 pub(crate) use crate::sys::{
     // MenuBuilder<T> -> Result<MenuSys<T>, Error>
     build_menu,
@@ -32,25 +62,68 @@ pub(crate) use crate::sys::{
     // Struct
     MenuSys,
 
+    // Struct with `for_window`, `run`, `wake` and `quit` for the message loop of
+    // a detached tray icon
+    MessageThread,
+
     // Struct that must implement TrayIconBase
     TrayIconSys,
 };
 
+/// Is the system using a high contrast theme
+///
+/// Use it to choose e.g. a bolder monochrome icon, changes can be observed with
+/// `TrayIconBuilder::on_high_contrast_changed`.
+pub fn is_high_contrast() -> bool {
+    crate::sys::is_high_contrast()
+}
+
 /// TrayIconSys must implement this
 pub(crate) trait TrayIconBase<T>
 where
     T: PartialEq + Clone + 'static,
 {
     fn set_icon(&mut self, icon: &Icon) -> Result<(), Error>;
+    fn set_icon_coalesced(&mut self, icon: &Icon) -> Result<(), Error>;
     fn set_menu(&mut self, menu: &MenuBuilder<T>) -> Result<(), Error>;
     fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error>;
+    fn update(&mut self, update: &TrayIconUpdate) -> Result<(), Error>;
+    fn show_notification(
+        &mut self,
+        title: &str,
+        message: &str,
+        options: &NotificationOptions,
+    ) -> Result<(), Error>;
+    fn clear_notification(&mut self) -> Result<(), Error>;
+    fn pump_messages(&mut self);
+    fn is_visible(&self) -> bool;
+    fn icon_rect(&self) -> Option<Rect>;
+    fn last_os_error(&self) -> Option<u32>;
+    fn request_show(&mut self) -> Result<(), Error>;
+    fn remove(&mut self) -> Result<(), Error>;
+    fn restore(&mut self) -> Result<(), Error>;
+    fn set_interactive(&mut self, interactive: bool);
+    fn set_quit_on_close(&mut self, quit: bool);
+    fn block_shutdown(&mut self, reason: Option<&str>) -> Result<(), Error>;
+    fn set_sender(&mut self, sender: trayiconsender::TrayIconSender<T>);
+    fn show_menu_at(&mut self, x: i32, y: i32) -> Result<Option<T>, Error>;
+    fn open_menu(&mut self) -> Result<Option<T>, Error>;
+    fn window_handle(&self) -> usize;
+    fn is_on_message_thread(&self) -> bool;
+    fn with_notify_data(&mut self, f: &mut dyn FnMut(*mut std::ffi::c_void)) -> Result<(), Error>;
+    #[cfg(feature = "raw-window-handle")]
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle;
+    #[cfg(any(test, feature = "testing"))]
+    fn simulate(&self, input: &SimulatedInput<T>) -> Result<(), Error>;
 }
 
 /// IconSys must implement this
 pub(crate) trait IconBase {
     fn from_buffer(
-        buffer: &'static [u8],
+        buffer: &[u8],
         width: Option<u32>,
         height: Option<u32>,
     ) -> Result<IconSys, Error>;
+    fn from_sizes(images: &[(u32, Vec<u8>)]) -> Result<IconSys, Error>;
+    fn from_stock(stock: StockIcon) -> Result<IconSys, Error>;
 }
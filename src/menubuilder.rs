@@ -1,4 +1,5 @@
 use crate::{Error, Icon};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MenuItem<T>
@@ -34,6 +35,40 @@ where
     T: PartialEq + Clone + 'static,
 {
     pub(crate) menu_items: Vec<MenuItem<T>>,
+    pub(crate) default_item: Option<T>,
+    pub(crate) menu_id: Option<MenuIdFn<T>>,
+}
+
+/// `MenuId::menu_id` of the event type, it's the only function stored so they
+/// are all equal
+pub(crate) struct MenuIdFn<T>(fn(&T) -> u16);
+
+impl<T> Clone for MenuIdFn<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for MenuIdFn<T> {}
+
+impl<T> PartialEq for MenuIdFn<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> std::fmt::Debug for MenuIdFn<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MenuIdFn")
+    }
+}
+
+/// Stable command id of a menu event, see `MenuBuilder::stable_ids`
+///
+/// E.g. for a fieldless enum `*self as u16 + 1`, ids must be unique within the
+/// menu and not zero.
+pub trait MenuId {
+    fn menu_id(&self) -> u16;
 }
 
 /// Menu Builder
@@ -41,12 +76,24 @@ where
 /// This is defined as consuming builder, could be converted to non-consuming
 /// one. This builder includes conditional helper `when` for composing
 /// conditionally some items.
+///
+/// The builder is only a definition of the menu, the OS menu is created when
+/// it's given to a tray icon. Same definition can be cloned and reused for
+/// several tray icons or rebuilds.
+///
+/// Items are found by their event with `PartialEq`, e.g. in `set_checkable`
+/// and `default_item`, and menus are compared to skip unchanged `set_menu`
+/// calls. Give each item a distinct event, the first match wins.
 impl<T> MenuBuilder<T>
 where
     T: PartialEq + Clone + 'static,
 {
     pub fn new() -> MenuBuilder<T> {
-        MenuBuilder { menu_items: vec![] }
+        MenuBuilder {
+            menu_items: vec![],
+            default_item: None,
+            menu_id: None,
+        }
     }
 
     /// Conditionally include items, poor mans function composition
@@ -57,6 +104,26 @@ where
         f(self)
     }
 
+    /// Menu of plain items from name and event pairs
+    ///
+    /// Useful for menus defined at runtime, e.g. in a configuration file. For
+    /// separators and other kinds of items collect `MenuItem`s instead.
+    pub fn from_items<I, S>(items: I) -> MenuBuilder<T>
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+    {
+        items
+            .into_iter()
+            .map(|(name, id)| MenuItem::Item {
+                id,
+                name: name.into(),
+                disabled: false,
+                icon: None,
+            })
+            .collect()
+    }
+
     pub fn with(mut self, item: MenuItem<T>) -> Self {
         self.menu_items.push(item);
         self
@@ -99,10 +166,74 @@ where
         self
     }
 
+    /// Show the item with the event in bold as the default action
+    ///
+    /// Only items of the top level menu can be the default. Double click
+    /// selects it if `TrayIconBuilder::double_click_default_item` is set.
+    pub fn default_item(mut self, id: T) -> Self {
+        self.default_item = Some(id);
+        self
+    }
+
+    /// Items of the menu
+    pub fn items(&self) -> &[MenuItem<T>] {
+        &self.menu_items
+    }
+
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
     pub(crate) fn build(&self) -> Result<crate::MenuSys<T>, Error> {
         crate::build_menu(self)
     }
 
+    /// Use the ids of the events as the command ids of the items
+    ///
+    /// By default items are numbered in order, so the ids change when items
+    /// are added or removed. With this the ids come from `MenuId`, and stay the
+    /// same across rebuilds. Applies to the whole menu, including submenus.
+    /// Building the menu fails with `Error::InvalidMenuId` if the ids aren't
+    /// unique or an id is zero.
+    pub fn stable_ids(mut self) -> Self
+    where
+        T: MenuId,
+    {
+        self.menu_id = Some(MenuIdFn(|e| e.menu_id()));
+        self
+    }
+
+    /// Command ids of the menu items mapped to their events
+    ///
+    /// Ids are assigned in order starting from 1, submenu items are numbered
+    /// after the submenu itself, or with `stable_ids` from the events. OS
+    /// specific menus must number their items with `next_command_id`.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub(crate) fn command_ids(&self) -> HashMap<usize, T> {
+        let mut map = HashMap::new();
+        self._command_ids_recurse(&mut 0, &mut map, self.menu_id);
+        map
+    }
+
+    fn _command_ids_recurse(
+        &self,
+        j: &mut usize,
+        map: &mut HashMap<usize, T>,
+        menu_id: Option<MenuIdFn<T>>,
+    ) {
+        for item in &self.menu_items {
+            match item {
+                MenuItem::Submenu { id, children, .. } => {
+                    if let Some(id) = id {
+                        map.insert(next_command_id(j, id, menu_id), id.clone());
+                    }
+                    children._command_ids_recurse(j, map, menu_id);
+                }
+                MenuItem::Checkable { id, .. } | MenuItem::Item { id, .. } => {
+                    map.insert(next_command_id(j, id, menu_id), id.clone());
+                }
+                MenuItem::Separator => {}
+            }
+        }
+    }
+
     /// Get checkable state, if found.
     ///
     /// Prefer maintaining proper application state instead of getting checkable
@@ -182,20 +313,43 @@ where
             f(item)
         } else {
             // Try to recurse, if submenus exist
-            let maybe_found_submenu = self.menu_items.iter_mut().find(|i| match i {
-                MenuItem::Submenu { .. } => true,
-                _ => false,
-            });
-            if let Some(found_submenu) = maybe_found_submenu {
-                if let MenuItem::Submenu { children, .. } = found_submenu {
-                    return children._mutate_item_recurse_ref(find_id, f);
-                }
+            let maybe_found_submenu = self
+                .menu_items
+                .iter_mut()
+                .find(|i| matches!(i, MenuItem::Submenu { .. }));
+            if let Some(MenuItem::Submenu { children, .. }) = maybe_found_submenu {
+                return children._mutate_item_recurse_ref(find_id, f);
             }
             Err(Error::MenuItemNotFound)
         }
     }
 }
 
+impl<T> std::iter::FromIterator<MenuItem<T>> for MenuBuilder<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn from_iter<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = MenuItem<T>>,
+    {
+        MenuBuilder {
+            menu_items: items.into_iter().collect(),
+            default_item: None,
+            menu_id: None,
+        }
+    }
+}
+
+/// Command id of the next item, `j` counts the items
+pub(crate) fn next_command_id<T>(j: &mut usize, id: &T, menu_id: Option<MenuIdFn<T>>) -> usize {
+    *j += 1;
+    match menu_id {
+        Some(f) => (f.0)(id) as usize,
+        None => *j,
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -247,4 +401,94 @@ pub(crate) mod tests {
         let _ = old.set_checkable(Events::CheckItem2, true);
         assert_eq!(old, menu_builder(true, true));
     }
+
+    impl MenuId for Events {
+        fn menu_id(&self) -> u16 {
+            *self as u16 + 100
+        }
+    }
+
+    #[test]
+    fn test_menu_stable_ids() {
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .separator()
+            .submenu(
+                "Sub Menu",
+                MenuBuilder::new().item("Sub item 1", Events::SubItem1),
+            )
+            .item("Item 3", Events::Item3)
+            .stable_ids();
+        let ids = menu.command_ids();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids.get(&100), Some(&Events::Item1));
+        assert_eq!(ids.get(&107), Some(&Events::SubItem1));
+        assert_eq!(ids.get(&102), Some(&Events::Item3));
+    }
+
+    #[test]
+    fn test_menu_command_ids() {
+        let menu = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .separator()
+            .checkable("This is checkable", true, Events::CheckItem1)
+            .submenu(
+                "Sub Menu",
+                MenuBuilder::new()
+                    .item("Sub item 1", Events::SubItem1)
+                    .item("Sub Item 2", Events::SubItem2),
+            )
+            .item("Item 2", Events::Item2);
+
+        assert_eq!(menu.items().len(), 5);
+
+        let ids = menu.command_ids();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(ids.get(&1), Some(&Events::Item1));
+        assert_eq!(ids.get(&2), Some(&Events::CheckItem1));
+        assert_eq!(ids.get(&3), Some(&Events::SubItem1));
+        assert_eq!(ids.get(&4), Some(&Events::SubItem2));
+        assert_eq!(ids.get(&5), Some(&Events::Item2));
+
+        // Zero means no selection
+        assert!(!ids.contains_key(&0));
+    }
+
+    #[test]
+    fn test_menu_clone_is_independent() {
+        let definition = MenuBuilder::new()
+            .checkable("This is checkable", false, Events::CheckItem1)
+            .item("Item 1", Events::Item1);
+
+        let mut copy = definition.clone();
+        assert_eq!(copy, definition);
+
+        let _ = copy.set_checkable(Events::CheckItem1, true);
+        assert_eq!(copy.get_checkable(Events::CheckItem1), Some(true));
+        assert_eq!(
+            definition.clone().get_checkable(Events::CheckItem1),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_menu_from_items() {
+        let menu =
+            MenuBuilder::from_items(vec![("Item 1", Events::Item1), ("Item 2", Events::Item2)]);
+        assert_eq!(
+            menu,
+            MenuBuilder::new()
+                .item("Item 1", Events::Item1)
+                .item("Item 2", Events::Item2)
+        );
+
+        let menu = menu
+            .items()
+            .iter()
+            .cloned()
+            .chain(vec![MenuItem::Separator])
+            .collect::<MenuBuilder<_>>();
+        assert_eq!(menu.items().len(), 3);
+        assert_eq!(menu.items()[2], MenuItem::Separator);
+    }
 }
@@ -0,0 +1,36 @@
+use crate::{Error, MenuSys};
+
+/// Menu about to open, see `TrayIconBuilder::on_menu_init`
+///
+/// Changes apply only to the menu that is opening, they aren't stored in the
+/// menu of the builder. Setting a new menu with `TrayIcon::set_menu` restores
+/// the states of the builder.
+pub struct MenuInit<'a, T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    pub(crate) menu: &'a MenuSys<T>,
+}
+
+impl<'a, T> MenuInit<'a, T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Check or uncheck the item
+    pub fn set_checked(&mut self, id: T, checked: bool) -> Result<(), Error> {
+        if self.menu.set_checked(&id, checked) {
+            Ok(())
+        } else {
+            Err(Error::MenuItemNotFound)
+        }
+    }
+
+    /// Disable or enable the item
+    pub fn set_disabled(&mut self, id: T, disabled: bool) -> Result<(), Error> {
+        if self.menu.set_disabled(&id, disabled) {
+            Ok(())
+        } else {
+            Err(Error::MenuItemNotFound)
+        }
+    }
+}
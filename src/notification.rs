@@ -0,0 +1,66 @@
+/// Icon of a balloon notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotificationIcon {
+    #[default]
+    None,
+    Info,
+    Warning,
+    Error,
+
+    /// Icon of the tray icon
+    TrayIcon,
+}
+
+/// Options of a balloon notification, see `TrayIcon::show_notification_with`
+///
+/// Defaults show the notification as soon as possible, queued behind other
+/// notifications and during quiet time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotificationOptions {
+    pub(crate) realtime: bool,
+    pub(crate) respect_quiet_time: bool,
+    pub(crate) icon: NotificationIcon,
+    pub(crate) silent: bool,
+    pub(crate) large_icon: bool,
+}
+
+impl NotificationOptions {
+    /// Discard the notification if it can't be shown immediately
+    ///
+    /// E.g. when another notification is showing, or a full screen application
+    /// or presentation is running. Without this the notification is queued.
+    /// This doesn't force the notification through, for urgent alerts leave it
+    /// off so that they are shown when possible.
+    pub fn realtime(&mut self, realtime: bool) -> &mut Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Don't show the notification during quiet time
+    ///
+    /// Quiet time is the first hour after a new user logs in for the first
+    /// time. Independent of `realtime`, both are off by default so urgent
+    /// alerts aren't suppressed by accident.
+    pub fn respect_quiet_time(&mut self, respect: bool) -> &mut Self {
+        self.respect_quiet_time = respect;
+        self
+    }
+
+    /// Icon shown next to the title, none by default
+    pub fn icon(&mut self, icon: NotificationIcon) -> &mut Self {
+        self.icon = icon;
+        self
+    }
+
+    /// Don't play the notification sound
+    pub fn silent(&mut self, silent: bool) -> &mut Self {
+        self.silent = silent;
+        self
+    }
+
+    /// Show the icon in the large size, only with `NotificationIcon::TrayIcon`
+    pub fn large_icon(&mut self, large: bool) -> &mut Self {
+        self.large_icon = large;
+        self
+    }
+}
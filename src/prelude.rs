@@ -0,0 +1,3 @@
+//! Commonly used types, `use trayicon::prelude::*;`
+
+pub use crate::{Error, Icon, MenuBuilder, MenuItem, TrayIcon, TrayIconBuilder};
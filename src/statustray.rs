@@ -0,0 +1,73 @@
+use crate::{Error, Icon, TrayIcon};
+
+/// Status shown by `StatusTray`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// Tray icon with three states, each with own icon and tooltip
+///
+/// Thin helper over `TrayIcon::update` for monitoring tools and such, the icon
+/// and the tooltip change together.
+pub struct StatusTray<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    tray_icon: TrayIcon<T>,
+    states: [(Icon, String); 3],
+    status: Status,
+}
+
+impl<T> StatusTray<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Wrap the tray icon, and show the ok status
+    pub fn new(
+        tray_icon: TrayIcon<T>,
+        ok: (Icon, &str),
+        warning: (Icon, &str),
+        error: (Icon, &str),
+    ) -> Result<StatusTray<T>, Error> {
+        let mut status_tray = StatusTray {
+            tray_icon,
+            states: [
+                (ok.0, ok.1.to_string()),
+                (warning.0, warning.1.to_string()),
+                (error.0, error.1.to_string()),
+            ],
+            status: Status::Ok,
+        };
+        status_tray.apply(Status::Ok)?;
+        Ok(status_tray)
+    }
+
+    /// Set the status if changed
+    ///
+    /// On error the previous status is kept, and setting the same status
+    /// again retries.
+    pub fn set_status(&mut self, status: Status) -> Result<(), Error> {
+        self.apply(status)
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Access the tray icon, e.g. for changing the menu
+    pub fn tray_icon(&mut self) -> &mut TrayIcon<T> {
+        &mut self.tray_icon
+    }
+
+    fn apply(&mut self, status: Status) -> Result<(), Error> {
+        let (icon, tooltip) = &self.states[status as usize];
+        self.tray_icon.update(|u| {
+            u.icon(icon).tooltip(tooltip);
+        })?;
+        self.status = status;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+// Stand-in for platforms without a tray icon implementation
+//
+// The crate builds so that the platform independent parts, e.g. the menu
+// model, can be used and tested. Nothing can be created, building fails with
+// `Error::Unsupported` and the types can't have values.
+
+use crate::{
+    trayiconsender::TrayIconSender, Error, Icon, IconBase, MenuBuilder, NotificationOptions, Rect,
+    StockIcon, TrayIconBase, TrayIconBuilder, TrayIconUpdate,
+};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone)]
+pub struct IconSys(Infallible);
+
+impl IconBase for IconSys {
+    fn from_buffer(
+        _buffer: &[u8],
+        _width: Option<u32>,
+        _height: Option<u32>,
+    ) -> Result<IconSys, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn from_sizes(_images: &[(u32, Vec<u8>)]) -> Result<IconSys, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn from_stock(_stock: StockIcon) -> Result<IconSys, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+#[derive(Debug)]
+pub struct MenuSys<T>(Infallible, PhantomData<T>);
+
+impl<T> MenuSys<T> {
+    pub(crate) fn set_checked(&self, _id: &T, _checked: bool) -> bool {
+        match self.0 {}
+    }
+
+    pub(crate) fn set_disabled(&self, _id: &T, _disabled: bool) -> bool {
+        match self.0 {}
+    }
+}
+
+/// Message loop of a detached tray icon, there's no loop to run
+#[derive(Debug, Clone, Copy)]
+pub struct MessageThread;
+
+impl MessageThread {
+    pub fn for_window(_hwnd: usize) -> MessageThread {
+        MessageThread
+    }
+
+    pub fn run<F>(&self, _on_wake: F)
+    where
+        F: FnMut(),
+    {
+    }
+
+    pub fn wake(&self) -> bool {
+        false
+    }
+
+    pub fn quit(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct TrayIconSys<T>(Infallible, PhantomData<T>);
+
+impl<T> TrayIconSys<T> {
+    fn never(&self) -> ! {
+        match self.0 {}
+    }
+}
+
+impl<T> TrayIconBase<T> for TrayIconSys<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn set_icon(&mut self, _icon: &Icon) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn set_icon_coalesced(&mut self, _icon: &Icon) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn set_menu(&mut self, _menu: &MenuBuilder<T>) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn set_tooltip(&mut self, _tooltip: &str) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn update(&mut self, _update: &TrayIconUpdate) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn show_notification(
+        &mut self,
+        _title: &str,
+        _message: &str,
+        _options: &NotificationOptions,
+    ) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn clear_notification(&mut self) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn pump_messages(&mut self) {
+        self.never()
+    }
+
+    fn is_visible(&self) -> bool {
+        self.never()
+    }
+
+    fn icon_rect(&self) -> Option<Rect> {
+        self.never()
+    }
+
+    fn last_os_error(&self) -> Option<u32> {
+        self.never()
+    }
+
+    fn request_show(&mut self) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn remove(&mut self) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn restore(&mut self) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn set_interactive(&mut self, _interactive: bool) {
+        self.never()
+    }
+
+    fn set_quit_on_close(&mut self, _quit: bool) {
+        self.never()
+    }
+
+    fn block_shutdown(&mut self, _reason: Option<&str>) -> Result<(), Error> {
+        self.never()
+    }
+
+    fn set_sender(&mut self, _sender: TrayIconSender<T>) {
+        self.never()
+    }
+
+    fn show_menu_at(&mut self, _x: i32, _y: i32) -> Result<Option<T>, Error> {
+        self.never()
+    }
+
+    fn open_menu(&mut self) -> Result<Option<T>, Error> {
+        self.never()
+    }
+
+    fn window_handle(&self) -> usize {
+        self.never()
+    }
+
+    fn is_on_message_thread(&self) -> bool {
+        self.never()
+    }
+
+    fn with_notify_data(&mut self, _f: &mut dyn FnMut(*mut std::ffi::c_void)) -> Result<(), Error> {
+        self.never()
+    }
+
+    #[cfg(feature = "raw-window-handle")]
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        self.never()
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    fn simulate(&self, _input: &crate::SimulatedInput<T>) -> Result<(), Error> {
+        self.never()
+    }
+}
+
+/// Menus can't be built without the platform menu
+pub fn build_menu<T>(_builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+where
+    T: PartialEq + Clone + 'static,
+{
+    Err(Error::Unsupported)
+}
+
+pub fn build_trayicon<T>(_builder: &TrayIconBuilder<T>) -> Result<Box<TrayIconSys<T>>, Error>
+where
+    T: PartialEq + Clone + 'static,
+{
+    Err(Error::Unsupported)
+}
+
+pub fn is_high_contrast() -> bool {
+    false
+}
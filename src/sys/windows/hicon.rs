@@ -1,64 +1,320 @@
-use crate::{Error, IconBase};
+use crate::{Error, IconBase, StockIcon};
+use std::sync::Arc;
 use winapi::shared::minwindef::PBYTE;
-use winapi::shared::windef::HICON;
-use winapi::um::winuser;
+use winapi::shared::windef::{HICON, HWND};
+use winapi::um::{errhandlingapi, libloaderapi, shellapi, winuser};
 
-/// Purpose of this struct is to keep hicon handle, and drop it when the struct
-/// is dropped
+/// Purpose of this struct is to keep hicon handle, and drop it when the last
+/// clone of the struct is dropped
+///
+/// Clones share the same handle, so it's destroyed exactly once. Shared
+/// handles of the system are never destroyed.
+#[derive(Clone)]
 pub struct WinHIcon {
-    pub hicon: HICON,
+    handle: Arc<HIconHandle>,
+}
+
+struct HIconHandle {
+    hicon: HICON,
+
+    /// Handle is owned by the system, e.g. from `LoadIcon`
+    shared: bool,
+}
+
+impl WinHIcon {
+    /// Icon owning the handle
+    fn owned(hicon: HICON) -> WinHIcon {
+        WinHIcon {
+            handle: Arc::new(HIconHandle {
+                hicon,
+                shared: false,
+            }),
+        }
+    }
+
+    /// Icon with a shared handle of the system, it's never destroyed
+    pub fn shared(hicon: HICON) -> WinHIcon {
+        WinHIcon {
+            handle: Arc::new(HIconHandle {
+                hicon,
+                shared: true,
+            }),
+        }
+    }
+
+    pub fn hicon(&self) -> HICON {
+        self.handle.hicon
+    }
+
+    /// Is the handle a shared one not owned by this icon
+    pub fn is_shared(&self) -> bool {
+        self.handle.shared
+    }
 }
 
 impl IconBase for WinHIcon {
     fn from_buffer(
-        buffer: &'static [u8],
+        buffer: &[u8],
         width: Option<u32>,
         height: Option<u32>,
     ) -> Result<WinHIcon, Error> {
-        let offset = unsafe {
-            winuser::LookupIconIdFromDirectoryEx(
-                buffer.as_ptr() as PBYTE,
-                1,
-                width.unwrap_or_default() as i32,
-                height.unwrap_or_default() as i32,
-                winuser::LR_DEFAULTCOLOR,
-            )
-        };
-        if offset <= 0 {
-            return Err(Error::IconLoadingFailed);
-        }
-        let icon_data = &buffer[offset as usize..];
+        let (default_width, default_height) = tray_icon_size();
+        let width = width.map(|v| v as i32).unwrap_or(default_width);
+        let height = height.map(|v| v as i32).unwrap_or(default_height);
+        let icon_data = icon_image(buffer, width, height).ok_or(Error::IconLoadingFailed)?;
+        unsafe { errhandlingapi::SetLastError(0) };
         let hicon = unsafe {
             winuser::CreateIconFromResourceEx(
                 icon_data.as_ptr() as PBYTE,
                 icon_data.len() as u32,
                 1,
                 0x30000,
-                width.unwrap_or_default() as i32,
-                height.unwrap_or_default() as i32,
+                width,
+                height,
                 winuser::LR_DEFAULTCOLOR,
             )
         };
         if hicon.is_null() {
-            return Err(Error::IconLoadingFailed);
+            return Err(last_error());
+        }
+        Ok(WinHIcon::owned(hicon))
+    }
+
+    fn from_sizes(images: &[(u32, Vec<u8>)]) -> Result<WinHIcon, Error> {
+        let (width, height) = tray_icon_size();
+        let size = width.max(height) as u32;
+        let i =
+            closest_size(images.iter().map(|(s, _)| *s), size).ok_or(Error::IconLoadingFailed)?;
+        WinHIcon::from_buffer(&images[i].1, Some(width as u32), Some(height as u32))
+    }
+
+    fn from_stock(stock: StockIcon) -> Result<WinHIcon, Error> {
+        let (siid, idi) = match stock {
+            StockIcon::Application => (shellapi::SIID_APPLICATION, winuser::IDI_APPLICATION),
+            StockIcon::Information => (shellapi::SIID_INFO, winuser::IDI_INFORMATION),
+            StockIcon::Warning => (shellapi::SIID_WARNING, winuser::IDI_WARNING),
+            StockIcon::Error => (shellapi::SIID_ERROR, winuser::IDI_ERROR),
+            StockIcon::Question => (shellapi::SIID_HELP, winuser::IDI_QUESTION),
+            StockIcon::Shield => (shellapi::SIID_SHIELD, winuser::IDI_SHIELD),
+        };
+        let mut info: shellapi::SHSTOCKICONINFO = unsafe { std::mem::zeroed() };
+        info.cbSize = std::mem::size_of::<shellapi::SHSTOCKICONINFO>() as u32;
+        let res = unsafe {
+            shellapi::SHGetStockIconInfo(
+                siid,
+                shellapi::SHGSI_ICON | shellapi::SHGSI_SMALLICON,
+                &mut info,
+            )
+        };
+        if res == winapi::shared::winerror::S_OK && !info.hIcon.is_null() {
+            return Ok(WinHIcon::owned(info.hIcon));
+        }
+
+        // Stock icon API is available since Vista, system icons are shared
+        // and must not be destroyed
+        let hicon = unsafe { winuser::LoadIconW(std::ptr::null_mut(), idi) };
+        if hicon.is_null() {
+            return Err(last_error());
         }
-        Ok(WinHIcon { hicon })
+        Ok(WinHIcon::shared(hicon))
     }
 }
 
-impl Clone for WinHIcon {
-    fn clone(&self) -> Self {
-        WinHIcon {
-            hicon: unsafe { winuser::CopyIcon(self.hicon) },
+/// Index of the smallest size at least as large as the size, or the largest
+/// one, scaling down looks better than scaling up
+fn closest_size<I>(sizes: I, size: u32) -> Option<usize>
+where
+    I: Iterator<Item = u32>,
+{
+    sizes
+        .enumerate()
+        .min_by_key(|(_, s)| (*s < size, if *s < size { size - s } else { s - size }))
+        .map(|(i, _)| i)
+}
+
+/// Image to create the icon from
+///
+/// Whole `.ico` files start with the icon directory, the closest image is
+/// picked from it. Anything else is taken as a single icon image, PNG or a
+/// bitmap without the file header, as in the icon resources.
+fn icon_image(buffer: &[u8], width: i32, height: i32) -> Option<&[u8]> {
+    if buffer.starts_with(&[0, 0, 1, 0]) {
+        find_icon_image(buffer, width, height)
+    } else if buffer.is_empty() {
+        None
+    } else {
+        Some(buffer)
+    }
+}
+
+/// Image of the `.ico` file closest to the size
+///
+/// Prefers the smallest image at least as large as the size, and then the
+/// highest bit depth, so 32-bit images with alpha win over the older ones.
+///
+/// `LookupIconIdFromDirectoryEx` is meant for icon resources, where directory
+/// entries are 14 bytes with an id. In files the entries are 16 bytes with
+/// the offset of the image, so it misreads files with several images and
+/// images past 64 KiB.
+fn find_icon_image(buffer: &[u8], width: i32, height: i32) -> Option<&[u8]> {
+    let u16_at = |i: usize| Some(u16::from_le_bytes([*buffer.get(i)?, *buffer.get(i + 1)?]));
+    let u32_at = |i: usize| {
+        Some(u32::from_le_bytes([
+            *buffer.get(i)?,
+            *buffer.get(i + 1)?,
+            *buffer.get(i + 2)?,
+            *buffer.get(i + 3)?,
+        ]))
+    };
+
+    // ICONDIR header: reserved 0, type 1 for icons and the count of images
+    if u16_at(0)? != 0 || u16_at(2)? != 1 {
+        return None;
+    }
+    let count = u16_at(4)? as usize;
+    (0..count)
+        .filter_map(|i| {
+            let entry = 6 + i * 16;
+            let image_width = match *buffer.get(entry)? {
+                0 => 256,
+                v => v as i32,
+            };
+            let image_height = match *buffer.get(entry + 1)? {
+                0 => 256,
+                v => v as i32,
+            };
+            let bits = u16_at(entry + 6)?;
+            let size = u32_at(entry + 8)? as usize;
+            let offset = u32_at(entry + 12)? as usize;
+            let image = buffer.get(offset..offset.checked_add(size)?)?;
+            Some((image_width, image_height, bits, image))
+        })
+        .min_by_key(|(image_width, image_height, bits, _)| {
+            (
+                *image_width < width || *image_height < height,
+                (image_width - width).abs() + (image_height - height).abs(),
+                std::cmp::Reverse(*bits),
+            )
+        })
+        .map(|(_, _, _, image)| image)
+}
+
+/// Size of the tray icons on the monitor of the taskbar
+///
+/// Per monitor DPI aware processes must use the DPI of the taskbar instead of
+/// the primary monitor. The DPI functions are available since Windows 10 1607,
+/// so they are looked up at runtime.
+fn tray_icon_size() -> (i32, i32) {
+    type GetDpiForWindow = unsafe extern "system" fn(HWND) -> u32;
+    type GetSystemMetricsForDpi = unsafe extern "system" fn(i32, u32) -> i32;
+    unsafe {
+        let user32 = libloaderapi::GetModuleHandleA("user32.dll\0".as_ptr() as _);
+        let get_dpi = libloaderapi::GetProcAddress(user32, "GetDpiForWindow\0".as_ptr() as _);
+        let get_metrics =
+            libloaderapi::GetProcAddress(user32, "GetSystemMetricsForDpi\0".as_ptr() as _);
+        let taskbar = winuser::FindWindowA("Shell_TrayWnd\0".as_ptr() as _, std::ptr::null());
+        if !get_dpi.is_null() && !get_metrics.is_null() && !taskbar.is_null() {
+            let get_dpi: GetDpiForWindow = std::mem::transmute(get_dpi);
+            let get_metrics: GetSystemMetricsForDpi = std::mem::transmute(get_metrics);
+            let dpi = get_dpi(taskbar);
+            if dpi != 0 {
+                return (
+                    get_metrics(winuser::SM_CXSMICON, dpi),
+                    get_metrics(winuser::SM_CYSMICON, dpi),
+                );
+            }
         }
+        (
+            winuser::GetSystemMetrics(winuser::SM_CXSMICON),
+            winuser::GetSystemMetrics(winuser::SM_CYSMICON),
+        )
+    }
+}
+
+/// Error with the OS error code, if there is one
+fn last_error() -> Error {
+    match unsafe { errhandlingapi::GetLastError() } {
+        0 => Error::IconLoadingFailed,
+        code => Error::IconLoadingOsError(code),
     }
 }
 
-unsafe impl Send for WinHIcon {}
-unsafe impl Sync for WinHIcon {}
+unsafe impl Send for HIconHandle {}
+unsafe impl Sync for HIconHandle {}
 
-impl Drop for WinHIcon {
+impl Drop for HIconHandle {
     fn drop(&mut self) {
-        unsafe { winuser::DestroyIcon(self.hicon) };
+        if !self.shared {
+            unsafe { winuser::DestroyIcon(self.hicon) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Icon file with images of the given sizes and bit depths, the image data
+    /// is a single byte with the index of the image
+    fn ico(images: &[(u8, u16)]) -> Vec<u8> {
+        let mut buffer = vec![0, 0, 1, 0];
+        buffer.extend(&(images.len() as u16).to_le_bytes());
+        for (i, (size, bits)) in images.iter().enumerate() {
+            buffer.extend(&[*size, *size, 0, 0, 1, 0]);
+            buffer.extend(&bits.to_le_bytes());
+            buffer.extend(&1u32.to_le_bytes());
+            buffer.extend(&((6 + images.len() * 16 + i) as u32).to_le_bytes());
+        }
+        buffer.extend((0..images.len()).map(|i| i as u8));
+        buffer
+    }
+
+    /// Ensure that the closest image at least as large is chosen
+    #[test]
+    fn test_find_icon_image_size() {
+        let buffer = ico(&[(16, 32), (32, 32), (0, 32)]);
+        assert_eq!(find_icon_image(&buffer, 16, 16), Some(&[0u8][..]));
+        assert_eq!(find_icon_image(&buffer, 20, 20), Some(&[1u8][..]));
+        assert_eq!(find_icon_image(&buffer, 256, 256), Some(&[2u8][..]));
+        assert_eq!(find_icon_image(&buffer, 512, 512), Some(&[2u8][..]));
+    }
+
+    /// Ensure that 32-bit images with alpha are preferred
+    #[test]
+    fn test_find_icon_image_bits() {
+        let buffer = ico(&[(16, 8), (16, 32), (16, 4)]);
+        assert_eq!(find_icon_image(&buffer, 16, 16), Some(&[1u8][..]));
+    }
+
+    /// Ensure that truncated or invalid buffers are rejected
+    #[test]
+    fn test_find_icon_image_invalid() {
+        let buffer = ico(&[(16, 32)]);
+        assert_eq!(find_icon_image(&buffer[..buffer.len() - 1], 16, 16), None);
+        assert_eq!(find_icon_image(&[1, 2, 3], 16, 16), None);
+        assert_eq!(find_icon_image(&[], 16, 16), None);
+    }
+
+    /// Ensure that the closest larger size is preferred
+    #[test]
+    fn test_closest_size() {
+        let sizes = [16, 32, 48];
+        assert_eq!(closest_size(sizes.iter().cloned(), 16), Some(0));
+        assert_eq!(closest_size(sizes.iter().cloned(), 20), Some(1));
+        assert_eq!(closest_size(sizes.iter().cloned(), 40), Some(2));
+        assert_eq!(closest_size(sizes.iter().cloned(), 64), Some(2));
+        assert_eq!(closest_size(sizes.iter().cloned(), 8), Some(0));
+        assert_eq!(closest_size(std::iter::empty(), 16), None);
+    }
+
+    /// Ensure that single images are used as they are
+    #[test]
+    fn test_icon_image_single() {
+        let png = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        assert_eq!(icon_image(&png, 16, 16), Some(&png[..]));
+        assert_eq!(icon_image(&[], 16, 16), None);
+
+        let buffer = ico(&[(16, 32), (32, 32)]);
+        assert_eq!(icon_image(&buffer, 32, 32), Some(&[1u8][..]));
     }
 }
@@ -86,8 +86,63 @@ impl WinHMenu {
         res >= 0
     }
 
-    pub fn track(&self, hwnd: HWND, x: i32, y: i32) {
-        unsafe { winuser::TrackPopupMenu(self.hmenu, 0, x, y, 0, hwnd, std::ptr::null_mut()) };
+    /// Set the default item by command id, it's shown in bold
+    pub fn set_default(&self, id: usize) -> bool {
+        unsafe { winuser::SetMenuDefaultItem(self.hmenu, id as u32, 0) != 0 }
+    }
+
+    /// Check or uncheck the item by command id, searches also the submenus
+    pub fn set_checked(&self, id: usize, checked: bool) -> bool {
+        let flags = if checked {
+            winuser::MF_CHECKED
+        } else {
+            winuser::MF_UNCHECKED
+        };
+        let res =
+            unsafe { winuser::CheckMenuItem(self.hmenu, id as u32, winuser::MF_BYCOMMAND | flags) };
+        res != u32::MAX
+    }
+
+    /// Disable or enable the item by command id, searches also the submenus
+    pub fn set_disabled(&self, id: usize, disabled: bool) -> bool {
+        let flags = if disabled {
+            winuser::MF_GRAYED
+        } else {
+            winuser::MF_ENABLED
+        };
+        let res = unsafe {
+            winuser::EnableMenuItem(self.hmenu, id as u32, winuser::MF_BYCOMMAND | flags)
+        };
+        res != -1
+    }
+
+    /// Show the menu, window should be foreground before calling this
+    ///
+    /// Returns the selected command id, or `None` if the menu was cancelled.
+    /// Command ids start from 1, since 0 means no selection.
+    ///
+    /// WM_NULL is posted after the menu closes, otherwise the menu may not
+    /// show up properly the next time.
+    ///
+    /// https://devblogs.microsoft.com/oldnewthing/20040315-00/?p=40253
+    pub fn track(&self, hwnd: HWND, x: i32, y: i32, flags: u32) -> Option<usize> {
+        let res = unsafe {
+            let res = winuser::TrackPopupMenu(
+                self.hmenu,
+                flags | winuser::TPM_RETURNCMD,
+                x,
+                y,
+                0,
+                hwnd,
+                std::ptr::null_mut(),
+            );
+            winuser::PostMessageA(hwnd, winuser::WM_NULL, 0, 0);
+            res
+        };
+        match res {
+            0 => None,
+            id => Some(id as usize),
+        }
     }
 }
 
@@ -1,18 +1,23 @@
 mod hicon;
 mod hmenu;
 mod notifyicon;
+mod thread;
+#[cfg(feature = "toast")]
+mod toast;
 mod wchar;
 mod window;
 
 use std::collections::HashMap;
 use window::TrayIconWindow;
 
-use crate::{Error, MenuBuilder, MenuItem, TrayIconBuilder};
+use crate::menubuilder::{next_command_id, MenuIdFn};
+use crate::{Error, MenuBuilder, MenuItem, Point, Rect, TrayIconBuilder};
 use hmenu::WinHMenu;
-use notifyicon::NotifyIcon;
+use winapi::shared::windef::{POINT, RECT};
 
 // Windows implementations of Icon, TrayIcon, and Menu
 pub use hicon::WinHIcon as IconSys;
+pub use thread::MessageThread;
 pub use window::TrayIconWindow as TrayIconSys;
 
 #[derive(Debug)]
@@ -22,6 +27,49 @@ where
 {
     ids: HashMap<usize, T>,
     menu: WinHMenu,
+    default_id: Option<usize>,
+}
+
+impl<T> MenuSys<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    /// Command id of the item with the event
+    fn command_id(&self, id: &T) -> Option<usize> {
+        self.ids.iter().find(|(_, e)| *e == id).map(|(k, _)| *k)
+    }
+
+    /// Check the item of the built menu without rebuilding it
+    pub(crate) fn set_checked(&self, id: &T, checked: bool) -> bool {
+        self.command_id(id)
+            .is_some_and(|k| self.menu.set_checked(k, checked))
+    }
+
+    /// Disable the item of the built menu without rebuilding it
+    pub(crate) fn set_disabled(&self, id: &T, disabled: bool) -> bool {
+        self.command_id(id)
+            .is_some_and(|k| self.menu.set_disabled(k, disabled))
+    }
+}
+
+impl From<POINT> for Point {
+    fn from(point: POINT) -> Point {
+        Point {
+            x: point.x,
+            y: point.y,
+        }
+    }
+}
+
+impl From<RECT> for Rect {
+    fn from(rect: RECT) -> Rect {
+        Rect {
+            x: rect.left,
+            y: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        }
+    }
 }
 
 /// Build the tray icon
@@ -29,28 +77,41 @@ pub fn build_trayicon<T>(builder: &TrayIconBuilder<T>) -> Result<Box<TrayIconWin
 where
     T: PartialEq + Clone + 'static,
 {
-    let mut menu: Option<MenuSys<T>> = None;
-    let tooltip = &builder.tooltip;
-    let hicon = &builder.icon.as_ref()?.sys;
-    let on_click = builder.on_click.clone();
-    let on_right_click = builder.on_right_click.clone();
-    let sender = builder.sender.clone().ok_or(Error::SenderMissing)?;
-    let on_double_click = builder.on_double_click.clone();
-    let notify_icon = NotifyIcon::new(hicon, tooltip);
-
-    // Try to get a popup menu
-    if let Some(rhmenu) = &builder.menu {
-        menu = Some(rhmenu.build()?);
-    }
-
-    Ok(TrayIconWindow::new(
-        sender,
-        menu,
-        notify_icon,
-        on_click,
-        on_double_click,
-        on_right_click,
-    )?)
+    if !is_shell_available() {
+        return Err(Error::ShellUnavailable);
+    }
+    TrayIconWindow::new(builder)
+}
+
+/// Can the process have tray icons
+///
+/// Session 0 is reserved for services and has no shell, the icons would never
+/// show up. In user sessions the shell may start later, the icons are added
+/// when the taskbar is created.
+fn is_shell_available() -> bool {
+    let mut session = 0;
+    let res = unsafe {
+        winapi::um::processthreadsapi::ProcessIdToSessionId(
+            winapi::um::processthreadsapi::GetCurrentProcessId(),
+            &mut session,
+        )
+    };
+    res == 0 || session != 0
+}
+
+/// Is high contrast theme on
+pub fn is_high_contrast() -> bool {
+    let mut hc: winapi::um::winuser::HIGHCONTRASTW = unsafe { std::mem::zeroed() };
+    hc.cbSize = std::mem::size_of::<winapi::um::winuser::HIGHCONTRASTW>() as u32;
+    let res = unsafe {
+        winapi::um::winuser::SystemParametersInfoW(
+            winapi::um::winuser::SPI_GETHIGHCONTRAST,
+            hc.cbSize,
+            &mut hc as *mut _ as _,
+            0,
+        )
+    };
+    res != 0 && hc.dwFlags & winapi::um::winuser::HCF_HIGHCONTRASTON != 0
 }
 
 /// Build the menu from Windows HMENU
@@ -59,19 +120,39 @@ where
     T: PartialEq + Clone + 'static,
 {
     let mut j = 0;
-    build_menu_inner(&mut j, builder)
+    let ids = builder.command_ids();
+    let menu = build_menu_inner(&mut j, builder, builder.menu_id)?;
+
+    // Stable ids must not collide or be zero, zero means no selection
+    if ids.len() != j || ids.contains_key(&0) {
+        return Err(Error::InvalidMenuId);
+    }
+    let default_id = builder.default_item.as_ref().and_then(|default| {
+        ids.iter()
+            .find(|(_, e)| *e == default)
+            .map(|(id, _)| *id)
+            .filter(|id| menu.set_default(*id))
+    });
+    Ok(MenuSys {
+        ids,
+        menu,
+        default_id,
+    })
 }
 
 /// Recursive menu builder
 ///
 /// Having a j value as mutable reference it's capable of handling nested
-/// submenus
-fn build_menu_inner<T>(j: &mut usize, builder: &MenuBuilder<T>) -> Result<MenuSys<T>, Error>
+/// submenus. Numbering must match `MenuBuilder::command_ids`.
+fn build_menu_inner<T>(
+    j: &mut usize,
+    builder: &MenuBuilder<T>,
+    menu_id: Option<MenuIdFn<T>>,
+) -> Result<WinHMenu, Error>
 where
     T: PartialEq + Clone + 'static,
 {
     let mut hmenu = WinHMenu::new()?;
-    let mut map: HashMap<usize, T> = HashMap::new();
     builder.menu_items.iter().for_each(|item| match item {
         MenuItem::Submenu {
             id,
@@ -81,33 +162,29 @@ where
             ..
         } => {
             if let Some(id) = id {
-                *j += 1;
-                map.insert(*j, id.clone());
+                next_command_id(j, id, menu_id);
             }
-            if let Ok(menusys) = build_menu_inner(j, children) {
-                map.extend(menusys.ids.into_iter());
-                hmenu.add_child_menu(&name, menusys.menu, *disabled);
+            if let Ok(menu) = build_menu_inner(j, children, menu_id) {
+                hmenu.add_child_menu(name, menu, *disabled);
             }
         }
 
         MenuItem::Checkable {
+            id,
             name,
             is_checked,
-            id,
             disabled,
             ..
         } => {
-            *j += 1;
-            map.insert(*j, id.clone());
-            hmenu.add_checkable_item(&name, *is_checked, *j, *disabled);
+            let command_id = next_command_id(j, id, menu_id);
+            hmenu.add_checkable_item(name, *is_checked, command_id, *disabled);
         }
 
         MenuItem::Item {
-            name, id, disabled, ..
+            id, name, disabled, ..
         } => {
-            *j += 1;
-            map.insert(*j, id.clone());
-            hmenu.add_menu_item(&name, *j, *disabled);
+            let command_id = next_command_id(j, id, menu_id);
+            hmenu.add_menu_item(name, command_id, *disabled);
         }
 
         MenuItem::Separator => {
@@ -115,21 +192,25 @@ where
         }
     });
 
-    Ok(MenuSys {
-        ids: map,
-        menu: hmenu,
-    })
+    Ok(hmenu)
 }
 
 // For pattern matching, these are in own mod
 mod msgs {
     pub const WM_USER_CREATE: u32 = 0x400 + 1000;
     pub const WM_USER_TRAYICON: u32 = 0x400 + 1001;
+    pub const WM_USER_WAKE: u32 = 0x400 + 1002;
+    pub const WM_USER_APPBAR: u32 = 0x400 + 1003;
+    #[cfg(feature = "toast")]
+    pub const WM_USER_TOAST: u32 = 0x400 + 1004;
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::trayiconsender::TrayIconSender;
+    use crate::TrayIconBase;
+    use winapi::um::winuser;
 
     #[derive(Copy, Clone, Eq, PartialEq, Debug)]
     enum Events {
@@ -144,6 +225,14 @@ pub(crate) mod tests {
         SubSubItem3,
     }
 
+    /// Builder of a tray icon sending `Item1` on click
+    fn click_builder(s: std::sync::mpsc::Sender<Events>) -> TrayIconBuilder<Events> {
+        TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .on_click(Events::Item1)
+    }
+
     #[test]
     fn test_menu_build() {
         let cond = false;
@@ -179,4 +268,431 @@ pub(crate) mod tests {
             panic!()
         }
     }
+
+    #[test]
+    fn test_menu_default_item() {
+        let builder = MenuBuilder::new()
+            .item("Item 1", Events::Item1)
+            .item("Sub Item 1", Events::SubItem1)
+            .default_item(Events::SubItem1);
+        let menusys = build_menu(&builder).unwrap();
+        assert_eq!(menusys.default_id, Some(2));
+
+        let menusys = build_menu(&MenuBuilder::new().item("Item 1", Events::Item1)).unwrap();
+        assert_eq!(menusys.default_id, None);
+    }
+
+    /// Ensure that duplicate and zero stable ids are rejected
+    #[test]
+    fn test_menu_invalid_stable_ids() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Id(u16, &'static str);
+        impl crate::MenuId for Id {
+            fn menu_id(&self) -> u16 {
+                self.0
+            }
+        }
+
+        let valid = MenuBuilder::new()
+            .item("Item 1", Id(1, "a"))
+            .item("Item 2", Id(2, "b"))
+            .stable_ids();
+        assert!(build_menu(&valid).is_ok());
+
+        let duplicate = MenuBuilder::new()
+            .item("Item 1", Id(1, "a"))
+            .submenu("Sub Menu", MenuBuilder::new().item("Item 2", Id(1, "b")))
+            .stable_ids();
+        assert_eq!(build_menu(&duplicate).err(), Some(Error::InvalidMenuId));
+
+        let zero = MenuBuilder::new().item("Item 1", Id(0, "a")).stable_ids();
+        assert_eq!(build_menu(&zero).err(), Some(Error::InvalidMenuId));
+    }
+
+    /// Ensure that a message delivered during teardown sends no events
+    #[test]
+    fn test_message_after_drop() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use winapi::shared::basetsd::{DWORD_PTR, UINT_PTR};
+        use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+        use winapi::shared::windef::HWND;
+        use winapi::um::commctrl;
+
+        static SENT: AtomicBool = AtomicBool::new(false);
+
+        // Installed after the subclass of the tray icon, so it sees the
+        // messages first. The click is sent after the tray icon has handled
+        // WM_DESTROY, but before WM_NCDESTROY.
+        unsafe extern "system" fn destroy_hook(
+            hwnd: HWND,
+            msg: UINT,
+            wparam: WPARAM,
+            lparam: LPARAM,
+            _id: UINT_PTR,
+            _data: DWORD_PTR,
+        ) -> LRESULT {
+            let res = commctrl::DefSubclassProc(hwnd, msg, wparam, lparam);
+            match msg {
+                winuser::WM_DESTROY => {
+                    winuser::SendMessageA(
+                        hwnd,
+                        msgs::WM_USER_TRAYICON,
+                        0,
+                        winuser::WM_LBUTTONUP as _,
+                    );
+                    SENT.store(true, Ordering::SeqCst);
+                }
+                winuser::WM_NCDESTROY => {
+                    commctrl::RemoveWindowSubclass(hwnd, Some(destroy_hook), 1);
+                }
+                _ => {}
+            }
+            res
+        }
+
+        let (s, r) = std::sync::mpsc::channel();
+        let mut window = build_trayicon(&click_builder(s)).unwrap();
+        window.pump_messages();
+        unsafe { commctrl::SetWindowSubclass(window.hwnd(), Some(destroy_hook), 1, 0) };
+        drop(window);
+        assert!(SENT.load(Ordering::SeqCst));
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_block_shutdown() {
+        let (s, r) = std::sync::mpsc::channel();
+        let mut window =
+            build_trayicon(&click_builder(s).on_session_end_query(Events::SubItem1)).unwrap();
+        let query = |window: &TrayIconWindow<Events>| unsafe {
+            winuser::SendMessageA(window.hwnd(), winuser::WM_QUERYENDSESSION, 0, 0)
+        };
+        window.block_shutdown(Some("Saving")).unwrap();
+        assert_eq!(query(&window), 0);
+        window.block_shutdown(None).unwrap();
+        assert_eq!(query(&window), 1);
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            vec![Events::SubItem1, Events::SubItem1]
+        );
+    }
+
+    #[test]
+    fn test_session_end() {
+        let (s, r) = std::sync::mpsc::channel();
+        let builder = click_builder(s)
+            .on_session_end_query(Events::SubItem1)
+            .on_session_end(Events::SubItem2);
+        let window = build_trayicon(&builder).unwrap();
+        let end = |ending: usize| unsafe {
+            winuser::SendMessageA(window.hwnd(), winuser::WM_ENDSESSION, ending, 0)
+        };
+
+        // Cancelled session end sends nothing
+        end(0);
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![]);
+        end(1);
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::SubItem2]);
+    }
+
+    #[test]
+    fn test_display_only() {
+        let builder = TrayIconBuilder::<Events>::new()
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .tooltip("Display only");
+        assert!(build_trayicon(&builder).is_ok());
+
+        let builder = builder.on_click(Events::Item1);
+        assert_eq!(build_trayicon(&builder).err(), Some(Error::SenderMissing));
+    }
+
+    #[test]
+    fn test_callback_message() {
+        let (s, r) = std::sync::mpsc::channel();
+        let builder = click_builder(s).callback_message(winuser::WM_APP + 1);
+        let mut window = build_trayicon(&builder).unwrap();
+        window.simulate(&crate::SimulatedInput::Click).unwrap();
+        window.pump_messages();
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::Item1]);
+
+        let builder = builder.callback_message(winuser::WM_USER + 1);
+        assert_eq!(
+            build_trayicon(&builder).err(),
+            Some(Error::InvalidCallbackMessage)
+        );
+    }
+
+    #[test]
+    fn test_message_only_window_class() {
+        let (s, _r) = std::sync::mpsc::channel();
+        let builder = click_builder(s)
+            .window_class("TrayIconTestCls")
+            .message_only(true);
+        let window = build_trayicon(&builder).unwrap();
+        let found = unsafe {
+            winuser::FindWindowExA(
+                winuser::HWND_MESSAGE,
+                std::ptr::null_mut(),
+                "TrayIconTestCls\0".as_ptr() as _,
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(found, window.hwnd());
+    }
+
+    #[test]
+    fn test_menu_on_left_click_conflict() {
+        let (s, _r) = std::sync::mpsc::channel();
+        let builder = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .menu(MenuBuilder::new().item("Item 1", Events::Item1))
+            .menu_on_left_click(true);
+        assert!(build_trayicon(&builder).is_ok());
+
+        let builder = builder.on_click(Events::SubItem1);
+        assert_eq!(
+            build_trayicon(&builder).err(),
+            Some(Error::ConflictingClickEvents)
+        );
+    }
+
+    /// Ensure that the window class can be registered again after the windows
+    /// are destroyed and the class unregistered, as with a reloaded DLL
+    #[test]
+    fn test_create_and_destroy_loop() {
+        for _ in 0..5 {
+            let (s, r) = std::sync::mpsc::channel();
+            let mut window = build_trayicon(&click_builder(s)).unwrap();
+            window.simulate(&crate::SimulatedInput::Click).unwrap();
+            window.pump_messages();
+            assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::Item1]);
+        }
+    }
+
+    /// Does the shell have an icon with the window and id
+    fn has_icon_rect(hwnd: winapi::shared::windef::HWND, id: u32) -> bool {
+        let mut ident: winapi::um::shellapi::NOTIFYICONIDENTIFIER = unsafe { std::mem::zeroed() };
+        ident.cbSize = std::mem::size_of::<winapi::um::shellapi::NOTIFYICONIDENTIFIER>() as u32;
+        ident.hWnd = hwnd;
+        ident.uID = id;
+        let mut rect = unsafe { std::mem::zeroed() };
+        let res = unsafe { winapi::um::shellapi::Shell_NotifyIconGetRect(&ident, &mut rect) };
+        res == winapi::shared::winerror::S_OK
+    }
+
+    /// Ensure that the icon is removed when a panic unwinds past it
+    #[test]
+    fn test_remove_on_panic() {
+        let ident = std::cell::Cell::new(None);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut window = build_trayicon(
+                &TrayIconBuilder::<Events>::new()
+                    .icon_from_buffer(include_bytes!("../../testresource/icon1.ico")),
+            )
+            .unwrap();
+            window.pump_messages();
+            assert!(window.icon_rect().is_some());
+            ident.set(Some((window.hwnd(), window.icon_id())));
+            panic!("panic with the tray icon");
+        }));
+        assert!(res.is_err());
+
+        let (hwnd, id) = ident.get().unwrap();
+        assert!(!has_icon_rect(hwnd, id));
+    }
+
+    /// Set in the child process of `test_remove_on_handler_panic`
+    const PANIC_CHILD_VAR: &str = "TRAYICON_TEST_PANIC_CHILD";
+
+    /// Ensure that the icon is removed when a handler panics in the window
+    /// procedure, the process is aborted so the panic happens in a child
+    #[test]
+    fn test_remove_on_handler_panic() {
+        struct Handler;
+        impl crate::TrayEventHandler<Events> for Handler {
+            fn on_left_click(&mut self) {
+                panic!("panic in the handler");
+            }
+        }
+
+        if std::env::var_os(PANIC_CHILD_VAR).is_some() {
+            let mut window = build_trayicon(
+                &TrayIconBuilder::new()
+                    .handler(Handler)
+                    .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+                    .on_click(Events::Item1),
+            )
+            .unwrap();
+            window.pump_messages();
+            assert!(window.icon_rect().is_some());
+            println!("icon {} {}", window.hwnd() as usize, window.icon_id());
+            window.simulate(&crate::SimulatedInput::Click).unwrap();
+            window.pump_messages();
+            unreachable!("handler panic must abort the process");
+        }
+
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "sys::tests::test_remove_on_handler_panic",
+                "--exact",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(PANIC_CHILD_VAR, "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let line = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("icon "))
+            .expect("child process didn't add the icon");
+        let mut parts = line.split(' ').map(|part| part.parse::<usize>().unwrap());
+        let hwnd = parts.next().unwrap() as winapi::shared::windef::HWND;
+        let id = parts.next().unwrap() as u32;
+        assert!(!has_icon_rect(hwnd, id));
+    }
+
+    #[test]
+    fn test_shared_sender() {
+        let (s, r) = std::sync::mpsc::channel();
+        let first = build_trayicon(
+            &click_builder(s.clone()).menu(MenuBuilder::new().item("Sub Item 1", Events::SubItem1)),
+        )
+        .unwrap();
+        let second = build_trayicon(
+            &TrayIconBuilder::new()
+                .sender(s)
+                .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+                .on_click(Events::CheckableItem1)
+                .menu(MenuBuilder::new().item("Sub Item 2", Events::SubItem2)),
+        )
+        .unwrap();
+        assert_ne!(first.icon_id(), second.icon_id());
+        assert_ne!(first.hwnd(), second.hwnd());
+
+        first.simulate(&crate::SimulatedInput::Click).unwrap();
+        second.simulate(&crate::SimulatedInput::Click).unwrap();
+        second
+            .simulate(&crate::SimulatedInput::MenuItem(Events::SubItem2))
+            .unwrap();
+        first
+            .simulate(&crate::SimulatedInput::MenuItem(Events::SubItem1))
+            .unwrap();
+        unsafe {
+            let mut msg = std::mem::zeroed();
+            while winuser::PeekMessageA(&mut msg, 0 as _, 0, 0, winuser::PM_REMOVE) != 0 {
+                winuser::DispatchMessageA(&msg);
+            }
+        }
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            vec![
+                Events::Item1,
+                Events::CheckableItem1,
+                Events::SubItem2,
+                Events::SubItem1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_simulated_input() {
+        let (s, r) = std::sync::mpsc::channel();
+        let builder = click_builder(s)
+            .on_double_click(Events::SubItem1)
+            .menu(MenuBuilder::new().item("Sub Item 2", Events::SubItem2));
+        let mut window = build_trayicon(&builder).unwrap();
+
+        window.simulate(&crate::SimulatedInput::Click).unwrap();
+        window
+            .simulate(&crate::SimulatedInput::DoubleClick)
+            .unwrap();
+        window
+            .simulate(&crate::SimulatedInput::MenuItem(Events::SubItem2))
+            .unwrap();
+        assert_eq!(
+            window.simulate(&crate::SimulatedInput::MenuItem(Events::SubItem3)),
+            Err(Error::MenuItemNotFound)
+        );
+        window.pump_messages();
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            vec![Events::Item1, Events::SubItem1, Events::SubItem2]
+        );
+    }
+
+    #[test]
+    fn test_set_sender() {
+        let (s, r) = std::sync::mpsc::channel();
+        let mut window = build_trayicon(&click_builder(s)).unwrap();
+        let (s2, r2) = std::sync::mpsc::channel();
+        window.set_sender(TrayIconSender::Std(s2));
+
+        window.simulate(&crate::SimulatedInput::Click).unwrap();
+        window.pump_messages();
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![]);
+        assert_eq!(r2.try_iter().collect::<Vec<_>>(), vec![Events::Item1]);
+    }
+
+    #[test]
+    fn test_not_interactive() {
+        let (s, r) = std::sync::mpsc::channel();
+        let builder = click_builder(s)
+            .on_notification_timeout(Events::SubItem1)
+            .menu(MenuBuilder::new().item("Sub Item 2", Events::SubItem2));
+        let mut window = build_trayicon(&builder).unwrap();
+        window.set_interactive(false);
+
+        window.simulate(&crate::SimulatedInput::Click).unwrap();
+        window
+            .simulate(&crate::SimulatedInput::MenuItem(Events::SubItem2))
+            .unwrap();
+        assert_eq!(window.open_menu(), Ok(None));
+        unsafe {
+            winuser::SendMessageA(
+                window.hwnd(),
+                msgs::WM_USER_TRAYICON,
+                0,
+                winapi::um::shellapi::NIN_BALLOONTIMEOUT as _,
+            )
+        };
+        window.pump_messages();
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![Events::SubItem1]);
+    }
+
+    #[test]
+    fn test_event_handler() {
+        #[derive(Default)]
+        struct Handler {
+            calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+        }
+        impl crate::TrayEventHandler<Events> for Handler {
+            fn on_left_click(&mut self) {
+                self.calls.lock().unwrap().push("left".into());
+            }
+            fn on_menu(&mut self, event: &Events) {
+                self.calls.lock().unwrap().push(format!("{:?}", event));
+            }
+        }
+        let handler = Handler::default();
+        let calls = handler.calls.clone();
+
+        // No sender is needed with a handler
+        let builder = TrayIconBuilder::new()
+            .handler(handler)
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .on_click(Events::Item1)
+            .menu(MenuBuilder::new().item("Sub Item 2", Events::SubItem2));
+        let mut window = build_trayicon(&builder).unwrap();
+
+        window.simulate(&crate::SimulatedInput::Click).unwrap();
+        window
+            .simulate(&crate::SimulatedInput::MenuItem(Events::SubItem2))
+            .unwrap();
+        window.pump_messages();
+        assert_eq!(*calls.lock().unwrap(), vec!["left", "SubItem2"]);
+    }
 }
@@ -1,71 +1,384 @@
-use super::{hicon::WinHIcon, msgs, wchar::wchar_array};
+use super::{
+    hicon::WinHIcon,
+    msgs,
+    wchar::{wchar_array, wchar_array_ellipsis},
+};
+use crate::{Error, NotificationIcon, NotificationOptions, NotifyVersion, TrayIconBuilder};
 use std::fmt::Debug;
-use winapi::shared::windef::HWND;
+use std::sync::atomic::{AtomicU32, Ordering};
+use winapi::shared::windef::{HWND, RECT};
 
 /// Purpose of this struct is to retain NotifyIconDataW and remove it on drop
 pub struct NotifyIcon {
     winhicon: WinHIcon,
     nid: winapi::um::shellapi::NOTIFYICONDATAW,
+    version: NotifyVersion,
+    negotiated_version: u32,
+    added: bool,
+    hidden: bool,
+    notification_timeout: u32,
+    last_os_error: Option<u32>,
 }
 
 impl NotifyIcon {
-    pub fn new(winhicon: &WinHIcon, tooltip: &Option<String>) -> NotifyIcon {
-        static mut ICON_ID: u32 = 1000;
-        unsafe {
-            ICON_ID += 1;
-        }
+    pub fn new<T>(builder: &TrayIconBuilder<T>) -> Result<NotifyIcon, Error>
+    where
+        T: PartialEq + Clone + 'static,
+    {
+        static ICON_ID: AtomicU32 = AtomicU32::new(1000);
         let mut icon = NotifyIcon {
-            winhicon: winhicon.clone(),
+            winhicon: builder.icon.as_ref()?.sys.clone(),
             nid: unsafe { std::mem::zeroed() },
+            version: builder.notify_version,
+            negotiated_version: 0,
+            added: false,
+            hidden: false,
+            notification_timeout: builder
+                .notification_timeout
+                .map(|v| v.as_millis().min(u32::MAX as u128) as u32)
+                .unwrap_or_default(),
+            last_os_error: None,
         };
-        if let Some(tooltip) = tooltip {
-            wchar_array(tooltip, icon.nid.szTip.as_mut());
-        }
+        icon.copy_tooltip(builder.tooltip.as_deref().unwrap_or_default());
         icon.nid.cbSize = std::mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32;
-        icon.nid.uID = unsafe { ICON_ID };
-        icon.nid.uCallbackMessage = msgs::WM_USER_TRAYICON;
-        icon.nid.hIcon = icon.winhicon.hicon;
+        icon.nid.uID = ICON_ID.fetch_add(1, Ordering::Relaxed) + 1;
+        icon.nid.uCallbackMessage = match builder.callback_message {
+            Some(message) if (winapi::um::winuser::WM_APP..=0xBFFF).contains(&message) => message,
+            Some(_) => return Err(Error::InvalidCallbackMessage),
+            None => msgs::WM_USER_TRAYICON,
+        };
+        icon.nid.hIcon = icon.winhicon.hicon();
         icon.nid.uFlags = winapi::um::shellapi::NIF_MESSAGE
             | winapi::um::shellapi::NIF_ICON
-            | winapi::um::shellapi::NIF_TIP;
+            | winapi::um::shellapi::NIF_TIP
+            | winapi::um::shellapi::NIF_STATE;
+
+        // Version 4 shows the standard tooltip only with this
+        if icon.version == NotifyVersion::V4 {
+            icon.nid.uFlags |= winapi::um::shellapi::NIF_SHOWTIP;
+        }
+        if let Some(guid) = builder.guid {
+            icon.nid.guidItem = winapi::shared::guiddef::GUID {
+                Data1: (guid >> 96) as u32,
+                Data2: (guid >> 80) as u16,
+                Data3: (guid >> 64) as u16,
+                Data4: (guid as u64).to_be_bytes(),
+            };
+            icon.nid.uFlags |= winapi::um::shellapi::NIF_GUID;
+        }
+        icon.update_shared_state();
+
+        Ok(icon)
+    }
+
+    /// Negotiate the protocol version, falling back to older versions
+    ///
+    /// Version must be set each time the icon is added.
+    fn set_version(&mut self) {
+        let versions: &[u32] = match self.version {
+            NotifyVersion::V4 => &[
+                winapi::um::shellapi::NOTIFYICON_VERSION_4,
+                winapi::um::shellapi::NOTIFYICON_VERSION,
+            ],
+            NotifyVersion::V3 => &[winapi::um::shellapi::NOTIFYICON_VERSION],
+        };
+        self.negotiated_version = 0;
+        for version in versions {
+            unsafe { *self.nid.u.uVersion_mut() = *version };
+            let res = unsafe {
+                winapi::um::shellapi::Shell_NotifyIconW(
+                    winapi::um::shellapi::NIM_SETVERSION,
+                    &mut self.nid,
+                )
+            };
+            if res == 1 {
+                self.negotiated_version = *version;
+                break;
+            }
+        }
+    }
+
+    /// Copy the tooltip to the notify icon data
+    ///
+    /// Screen readers announce the icon by its tooltip, so an empty tooltip is
+    /// replaced with the name of the executable.
+    fn copy_tooltip(&mut self, tooltip: &str) {
+        if tooltip.is_empty() {
+            wchar_array_ellipsis(&default_tooltip(), self.nid.szTip.as_mut());
+        } else {
+            wchar_array_ellipsis(tooltip, self.nid.szTip.as_mut());
+        }
+    }
+
+    #[cfg(test)]
+    pub fn id(&self) -> u32 {
+        self.nid.uID
+    }
+
+    /// Message of the mouse events of the icon
+    pub fn callback_message(&self) -> u32 {
+        self.nid.uCallbackMessage
+    }
+
+    /// Are the callback messages in version 4 format
+    pub fn is_version_4(&self) -> bool {
+        self.negotiated_version == winapi::um::shellapi::NOTIFYICON_VERSION_4
+    }
 
-        icon
+    /// Set `NIS_SHAREDICON` if the icon handle is a shared one not owned by
+    /// the icon, and `NIS_HIDDEN` if the icon is hidden
+    ///
+    /// Handle is kept alive by `winhicon` as long as it's used by this icon.
+    fn update_shared_state(&mut self) {
+        self.nid.dwStateMask =
+            winapi::um::shellapi::NIS_SHAREDICON | winapi::um::shellapi::NIS_HIDDEN;
+        self.nid.dwState = 0;
+        if self.winhicon.is_shared() {
+            self.nid.dwState |= winapi::um::shellapi::NIS_SHAREDICON;
+        }
+        if self.hidden {
+            self.nid.dwState |= winapi::um::shellapi::NIS_HIDDEN;
+        }
     }
 }
 
 impl NotifyIcon {
-    pub fn add(&mut self, hwnd: HWND) -> bool {
-        self.nid.hWnd = hwnd;
-        let res = unsafe {
-            winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_ADD, &mut self.nid)
-        };
+    /// Send the notify icon data to the shell
+    ///
+    /// On failure the `GetLastError` code is stored for `last_os_error`.
+    fn notify(&mut self, message: u32) -> bool {
+        unsafe { winapi::um::errhandlingapi::SetLastError(0) };
+        let res = unsafe { winapi::um::shellapi::Shell_NotifyIconW(message, &mut self.nid) };
+        if res != 1 {
+            self.last_os_error = Some(unsafe { winapi::um::errhandlingapi::GetLastError() });
+        }
         res == 1
     }
 
+    /// Error code of the latest failed shell call
+    pub fn last_os_error(&self) -> Option<u32> {
+        self.last_os_error
+    }
+
+    /// Add the icon with all of the stored data
+    ///
+    /// Changes made before adding are stored and included here, so the icon
+    /// never appears without its icon or tooltip.
+    pub fn add(&mut self, hwnd: HWND) -> Result<(), Error> {
+        self.nid.hWnd = hwnd;
+        self.nid.hIcon = self.winhicon.hicon();
+        self.nid.uFlags |= winapi::um::shellapi::NIF_MESSAGE
+            | winapi::um::shellapi::NIF_ICON
+            | winapi::um::shellapi::NIF_TIP;
+        self.add_stored()
+    }
+
+    /// Add the icon with the current data, e.g. after remove
+    fn add_stored(&mut self) -> Result<(), Error> {
+        self.added = self.notify(winapi::um::shellapi::NIM_ADD);
+        if !self.added && self.nid.uFlags & winapi::um::shellapi::NIF_GUID != 0 {
+            // GUID is registered for another executable path, the icon can
+            // still be added with the id
+            self.nid.uFlags &= !winapi::um::shellapi::NIF_GUID;
+            self.added = self.notify(winapi::um::shellapi::NIM_ADD);
+        }
+        if !self.added {
+            return Err(Error::OsError);
+        }
+        self.set_version();
+        Ok(())
+    }
+
+    /// Remove the icon, does nothing if it's not added
+    ///
+    /// Deleting the same icon twice fails, so only the first remove or drop
+    /// deletes it.
     pub fn remove(&mut self) -> bool {
-        let res = unsafe {
-            winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_DELETE, &mut self.nid)
-        };
-        res == 1
+        if !self.added {
+            return true;
+        }
+        self.added = false;
+        self.notify(winapi::um::shellapi::NIM_DELETE)
+    }
+
+    /// Apply the changes, or keep them for `add` if not added yet
+    ///
+    /// Only the given flags are sent, so e.g. setting the tooltip never touches
+    /// the balloon or the other way around. `uFlags` keeps the flags for `add`,
+    /// the GUID is always sent since it identifies the icon.
+    fn modify(&mut self, flags: u32) -> bool {
+        if !self.added {
+            return true;
+        }
+        let add_flags = self.nid.uFlags;
+        self.nid.uFlags = flags
+            | (add_flags & (winapi::um::shellapi::NIF_SHOWTIP | winapi::um::shellapi::NIF_GUID));
+        let res = self.notify(winapi::um::shellapi::NIM_MODIFY);
+        self.nid.uFlags = add_flags;
+        res
+    }
+
+    /// Modify with the flags and fields set by the function
+    ///
+    /// The fields are kept and their flags are added to the flags of the add,
+    /// so adding the icon again sends them too. Balloon flags aren't kept, a
+    /// balloon is shown only once.
+    pub fn modify_with(&mut self, f: &mut dyn FnMut(*mut std::ffi::c_void)) -> bool {
+        let add_flags = self.nid.uFlags;
+        self.nid.uFlags = 0;
+        f(&mut self.nid as *mut _ as *mut std::ffi::c_void);
+        let flags = self.nid.uFlags;
+        self.nid.uFlags = add_flags
+            | (flags & !(winapi::um::shellapi::NIF_INFO | winapi::um::shellapi::NIF_REALTIME));
+        self.modify(flags)
     }
 
     pub fn set_icon(&mut self, winhicon: &WinHIcon) -> bool {
         self.winhicon = winhicon.clone();
-        self.nid.hIcon = self.winhicon.hicon;
-        let res = unsafe {
-            winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut self.nid)
-        };
-        res == 1
+        self.nid.hIcon = self.winhicon.hicon();
+        self.update_shared_state();
+        self.modify(winapi::um::shellapi::NIF_ICON | winapi::um::shellapi::NIF_STATE)
     }
 
     pub fn set_tooltip(&mut self, tooltip: &str) -> bool {
-        wchar_array(tooltip, self.nid.szTip.as_mut());
-        let res = unsafe {
-            winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut self.nid)
+        self.copy_tooltip(tooltip);
+        self.modify(winapi::um::shellapi::NIF_TIP)
+    }
+
+    /// Change the given properties with a single shell call
+    pub fn update(
+        &mut self,
+        winhicon: Option<&WinHIcon>,
+        tooltip: Option<&str>,
+        visible: Option<bool>,
+    ) -> bool {
+        let mut flags = 0;
+        if let Some(winhicon) = winhicon {
+            self.winhicon = winhicon.clone();
+            self.nid.hIcon = self.winhicon.hicon();
+            flags |= winapi::um::shellapi::NIF_ICON | winapi::um::shellapi::NIF_STATE;
+        }
+        if let Some(tooltip) = tooltip {
+            self.copy_tooltip(tooltip);
+            flags |= winapi::um::shellapi::NIF_TIP;
+        }
+        if let Some(visible) = visible {
+            self.hidden = !visible;
+            flags |= winapi::um::shellapi::NIF_STATE;
+        }
+        self.update_shared_state();
+        self.modify(flags)
+    }
+
+    /// Remove and add the icon again with hidden and shared states cleared
+    pub fn request_show(&mut self) -> Result<(), Error> {
+        self.remove();
+        self.hidden = false;
+        self.nid.dwState = 0;
+        self.nid.dwStateMask =
+            winapi::um::shellapi::NIS_HIDDEN | winapi::um::shellapi::NIS_SHAREDICON;
+        let res = self.add_stored();
+        self.update_shared_state();
+        res
+    }
+
+    /// Get the screen rectangle of the icon
+    ///
+    /// Returns `None` if the shell doesn't know the location of the icon.
+    pub fn get_rect(&self) -> Option<RECT> {
+        let mut ident: winapi::um::shellapi::NOTIFYICONIDENTIFIER = unsafe { std::mem::zeroed() };
+        ident.cbSize = std::mem::size_of::<winapi::um::shellapi::NOTIFYICONIDENTIFIER>() as u32;
+        ident.hWnd = self.nid.hWnd;
+        ident.uID = self.nid.uID;
+        if self.nid.uFlags & winapi::um::shellapi::NIF_GUID != 0 {
+            ident.guidItem = self.nid.guidItem;
+        }
+        let mut rect: RECT = unsafe { std::mem::zeroed() };
+        let res = unsafe { winapi::um::shellapi::Shell_NotifyIconGetRect(&ident, &mut rect) };
+        if res == winapi::shared::winerror::S_OK {
+            Some(rect)
+        } else {
+            None
+        }
+    }
+
+    /// Show a balloon notification
+    ///
+    /// Title and message are UTF-16 encoded and truncated to fit `szInfoTitle`
+    /// (64) and `szInfo` (256) including the null terminator.
+    ///
+    /// Shell ignores a new balloon while the previous one is still visible,
+    /// which is noticeable with identical texts in quick succession, thus the
+    /// previous one is hidden first.
+    ///
+    /// Realtime is a flag of the message, quiet time a flag of the balloon.
+    pub fn show_notification(
+        &mut self,
+        title: &str,
+        message: &str,
+        options: &NotificationOptions,
+    ) -> bool {
+        self.hide_notification();
+        wchar_array(title, self.nid.szInfoTitle.as_mut());
+        wchar_array(message, self.nid.szInfo.as_mut());
+        self.nid.dwInfoFlags = info_flags(options);
+        let flags = if options.realtime {
+            winapi::um::shellapi::NIF_REALTIME
+        } else {
+            0
         };
-        res == 1
+        self.modify_info(flags)
+    }
+
+    /// Hide the balloon notification, empty text removes it
+    pub fn hide_notification(&mut self) -> bool {
+        wchar_array("", self.nid.szInfoTitle.as_mut());
+        wchar_array("", self.nid.szInfo.as_mut());
+        self.nid.dwInfoFlags = 0;
+        self.modify_info(0)
+    }
+
+    /// Balloon can't be stored for `add` like the other changes, it would be
+    /// shown again each time the icon is added
+    ///
+    /// Timeout shares the union with the version, so it's set each time.
+    fn modify_info(&mut self, flags: u32) -> bool {
+        unsafe { *self.nid.u.uTimeout_mut() = self.notification_timeout };
+        self.added && self.modify(winapi::um::shellapi::NIF_INFO | flags)
     }
 }
+
+/// Balloon flags of the options
+///
+/// Without `hBalloonIcon` the user icon is the icon of the tray icon.
+fn info_flags(options: &NotificationOptions) -> u32 {
+    let mut flags = match options.icon {
+        NotificationIcon::None => winapi::um::shellapi::NIIF_NONE,
+        NotificationIcon::Info => winapi::um::shellapi::NIIF_INFO,
+        NotificationIcon::Warning => winapi::um::shellapi::NIIF_WARNING,
+        NotificationIcon::Error => winapi::um::shellapi::NIIF_ERROR,
+        NotificationIcon::TrayIcon => winapi::um::shellapi::NIIF_USER,
+    };
+    if options.large_icon && options.icon == NotificationIcon::TrayIcon {
+        flags |= winapi::um::shellapi::NIIF_LARGE_ICON;
+    }
+    if options.silent {
+        flags |= winapi::um::shellapi::NIIF_NOSOUND;
+    }
+    if options.respect_quiet_time {
+        flags |= winapi::um::shellapi::NIIF_RESPECT_QUIET_TIME;
+    }
+    flags
+}
+
+/// Name of the executable without the extension
+fn default_tooltip() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| path.file_stem().map(|v| v.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "TrayIcon".to_string())
+}
+
 unsafe impl Send for NotifyIcon {}
 unsafe impl Sync for NotifyIcon {}
 
@@ -77,11 +390,65 @@ impl Debug for NotifyIcon {
 
 impl Drop for NotifyIcon {
     fn drop(&mut self) {
-        unsafe {
-            winapi::um::shellapi::Shell_NotifyIconW(
-                winapi::um::shellapi::NIM_DELETE,
-                &mut self.nid,
-            );
-        }
+        self.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winapi::um::shellapi;
+
+    /// Ensure that the data of the first add has the icon, tooltip and message
+    #[test]
+    fn test_add_data() {
+        let builder = TrayIconBuilder::<u32>::new()
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .tooltip("Tooltip");
+        let icon = NotifyIcon::new(&builder).unwrap();
+        let flags = shellapi::NIF_MESSAGE | shellapi::NIF_ICON | shellapi::NIF_TIP;
+        assert_eq!(icon.nid.uFlags & flags, flags);
+        assert_eq!(icon.nid.uCallbackMessage, msgs::WM_USER_TRAYICON);
+        assert_eq!(icon.nid.hIcon, icon.winhicon.hicon());
+        assert!(!icon.nid.hIcon.is_null());
+        let tip: Vec<u16> = "Tooltip\0".encode_utf16().collect();
+        assert_eq!(&icon.nid.szTip[..tip.len()], &tip[..]);
+    }
+
+    /// Ensure that the flags set with `modify_with` are sent also when the icon
+    /// is added again, except the balloon
+    #[test]
+    fn test_modify_with_keeps_flags() {
+        let builder = TrayIconBuilder::<u32>::new()
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"))
+            .notify_version(crate::NotifyVersion::V3);
+        let mut icon = NotifyIcon::new(&builder).unwrap();
+        assert_eq!(icon.nid.uFlags & shellapi::NIF_SHOWTIP, 0);
+        icon.modify_with(&mut |nid| {
+            let nid = unsafe { &mut *(nid as *mut shellapi::NOTIFYICONDATAW) };
+            nid.uFlags = shellapi::NIF_SHOWTIP | shellapi::NIF_INFO;
+        });
+        assert_ne!(icon.nid.uFlags & shellapi::NIF_SHOWTIP, 0);
+        assert_eq!(icon.nid.uFlags & shellapi::NIF_INFO, 0);
+    }
+
+    /// Ensure that an owned icon isn't marked shared, though the builder and
+    /// the notify icon both have a clone of it
+    #[test]
+    fn test_owned_icon_not_shared() {
+        let builder = TrayIconBuilder::<u32>::new()
+            .icon_from_buffer(include_bytes!("../../testresource/icon1.ico"));
+        let mut icon = NotifyIcon::new(&builder).unwrap();
+        assert!(builder.icon.is_ok());
+        assert_eq!(icon.nid.dwState & shellapi::NIS_SHAREDICON, 0);
+
+        let hicon = unsafe {
+            winapi::um::winuser::LoadIconW(
+                std::ptr::null_mut(),
+                winapi::um::winuser::IDI_APPLICATION,
+            )
+        };
+        icon.set_icon(&WinHIcon::shared(hicon));
+        assert_ne!(icon.nid.dwState & shellapi::NIS_SHAREDICON, 0);
     }
 }
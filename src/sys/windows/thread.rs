@@ -0,0 +1,57 @@
+use super::msgs;
+use winapi::shared::windef::HWND;
+use winapi::um::winuser;
+
+/// Message loop thread of a detached tray icon
+///
+/// Wake-ups are posted to the window of the tray icon, thread messages would
+/// be lost in modal loops, e.g. while the menu is open.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageThread {
+    hwnd: usize,
+    thread_id: u32,
+}
+
+impl MessageThread {
+    /// Message thread of the window, the handle of `TrayIcon::window_handle`
+    pub fn for_window(hwnd: usize) -> MessageThread {
+        MessageThread {
+            hwnd,
+            thread_id: unsafe {
+                winuser::GetWindowThreadProcessId(hwnd as HWND, std::ptr::null_mut())
+            },
+        }
+    }
+
+    /// Run the message loop until `quit`, calling `on_wake` after each `wake`
+    ///
+    /// Must be called on the thread of the window. A modal loop handles the
+    /// wake-up inside the dispatch, so `on_wake` is called after every
+    /// message that may have hidden one.
+    pub fn run<F>(&self, mut on_wake: F)
+    where
+        F: FnMut(),
+    {
+        unsafe {
+            let mut msg = std::mem::zeroed();
+            while winuser::GetMessageA(&mut msg, 0 as _, 0, 0) > 0 {
+                if msg.hwnd as usize == self.hwnd && msg.message == msgs::WM_USER_WAKE {
+                    on_wake();
+                } else {
+                    winuser::TranslateMessage(&msg);
+                    winuser::DispatchMessageA(&msg);
+                    on_wake();
+                }
+            }
+        }
+    }
+
+    pub fn wake(&self) -> bool {
+        unsafe { winuser::PostMessageA(self.hwnd as HWND, msgs::WM_USER_WAKE, 0, 0) != 0 }
+    }
+
+    /// Quit the loop, `GetMessage` sees the quit message even in modal loops
+    pub fn quit(&self) -> bool {
+        unsafe { winuser::PostThreadMessageA(self.thread_id, winuser::WM_QUIT, 0, 0) != 0 }
+    }
+}
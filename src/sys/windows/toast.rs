@@ -0,0 +1,486 @@
+//! Toast notifications of the WinRT notification API, `toast` feature
+//!
+//! Toasts can have action buttons, unlike the balloons. Activation of the
+//! toast or a button is posted to the tray icon window as `WM_USER_TOAST`,
+//! `wparam` is zero for the toast itself and the button index plus one for
+//! the buttons.
+
+// Interfaces keep the names of the WinRT ABI
+#![allow(non_snake_case)]
+
+use super::msgs;
+use std::sync::atomic::{AtomicU32, Ordering};
+use winapi::shared::guiddef::{IsEqualGUID, GUID, REFIID};
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::{E_NOINTERFACE, E_POINTER, RPC_E_CHANGED_MODE, S_OK};
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::um::winnt::HRESULT;
+use winapi::um::winuser;
+use winapi::winrt::hstring::HSTRING;
+use winapi::winrt::inspectable::{IInspectable, IInspectableVtbl};
+use winapi::winrt::{roapi, winstring};
+use winapi::{Interface, RIDL};
+
+RIDL! {#[uuid(0x50ac103f, 0xd235, 0x4598, 0xbb, 0xef, 0x98, 0xfe, 0x4d, 0x1a, 0x3a, 0xd4)]
+interface IToastNotificationManagerStatics(IToastNotificationManagerStaticsVtbl):
+    IInspectable(IInspectableVtbl) {
+    fn CreateToastNotifier(
+        result: *mut *mut IToastNotifier,
+    ) -> HRESULT,
+    fn CreateToastNotifierWithId(
+        applicationId: HSTRING,
+        result: *mut *mut IToastNotifier,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x75927b93, 0x03f3, 0x41ec, 0x91, 0xd3, 0x6e, 0x5b, 0xac, 0x1b, 0x38, 0xe7)]
+interface IToastNotifier(IToastNotifierVtbl): IInspectable(IInspectableVtbl) {
+    fn Show(
+        notification: *mut IToastNotification,
+    ) -> HRESULT,
+    fn Hide(
+        notification: *mut IToastNotification,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x04124b20, 0x82c6, 0x4229, 0xb1, 0x09, 0xfd, 0x9e, 0xd4, 0x66, 0x2b, 0x53)]
+interface IToastNotificationFactory(IToastNotificationFactoryVtbl):
+    IInspectable(IInspectableVtbl) {
+    fn CreateToastNotification(
+        content: *mut IXmlDocument,
+        result: *mut *mut IToastNotification,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x997e2675, 0x059e, 0x4e60, 0x8b, 0x06, 0x17, 0x60, 0x91, 0x7c, 0x8b, 0x80)]
+interface IToastNotification(IToastNotificationVtbl): IInspectable(IInspectableVtbl) {
+    fn get_Content(
+        value: *mut *mut IXmlDocument,
+    ) -> HRESULT,
+    fn put_ExpirationTime(
+        value: *mut IInspectable,
+    ) -> HRESULT,
+    fn get_ExpirationTime(
+        value: *mut *mut IInspectable,
+    ) -> HRESULT,
+    fn add_Dismissed(
+        handler: *mut IUnknown,
+        token: *mut i64,
+    ) -> HRESULT,
+    fn remove_Dismissed(
+        token: i64,
+    ) -> HRESULT,
+    fn add_Activated(
+        handler: *mut IUnknown,
+        token: *mut i64,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xe3bf92f3, 0xc197, 0x436f, 0x82, 0x65, 0x06, 0x25, 0x82, 0x4f, 0x8d, 0xac)]
+interface IToastActivatedEventArgs(IToastActivatedEventArgsVtbl):
+    IInspectable(IInspectableVtbl) {
+    fn get_Arguments(
+        value: *mut HSTRING,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xf7f3a506, 0x1e87, 0x42d6, 0xbc, 0xfb, 0xb8, 0xc8, 0x09, 0xfa, 0x54, 0x94)]
+interface IXmlDocument(IXmlDocumentVtbl): IInspectable(IInspectableVtbl) {}}
+
+RIDL! {#[uuid(0x6cd0e74e, 0xee65, 0x4489, 0x9e, 0xbf, 0xca, 0x43, 0xe8, 0x7b, 0xa6, 0x37)]
+interface IXmlDocumentIO(IXmlDocumentIOVtbl): IInspectable(IInspectableVtbl) {
+    fn LoadXml(
+        xml: HSTRING,
+    ) -> HRESULT,
+}}
+
+/// `TypedEventHandler<ToastNotification, Object>` of the `Activated` event
+const IID_ACTIVATED_HANDLER: GUID = GUID {
+    Data1: 0xab54de2d,
+    Data2: 0x97d9,
+    Data3: 0x5528,
+    Data4: [0xb6, 0xad, 0x10, 0x5a, 0xfe, 0x15, 0x65, 0x30],
+};
+
+/// Handler is called on a thread pool thread, so it must be agile
+const IID_AGILE_OBJECT: GUID = GUID {
+    Data1: 0x94ea2b94,
+    Data2: 0xe9cc,
+    Data3: 0x49e0,
+    Data4: [0xc0, 0xff, 0xee, 0x64, 0xca, 0x8f, 0x5b, 0x90],
+};
+
+/// Owned WinRT string
+struct HString(HSTRING);
+
+impl HString {
+    fn new(string: &str) -> Option<HString> {
+        let string = string.encode_utf16().collect::<Vec<_>>();
+        let mut hstring = std::ptr::null_mut();
+        let res = unsafe {
+            winstring::WindowsCreateString(string.as_ptr(), string.len() as u32, &mut hstring)
+        };
+        if res != S_OK {
+            return None;
+        }
+        Some(HString(hstring))
+    }
+}
+
+impl Drop for HString {
+    fn drop(&mut self) {
+        unsafe { winstring::WindowsDeleteString(self.0) };
+    }
+}
+
+/// Owned COM interface pointer
+struct ComPtr<I: Interface>(*mut I);
+
+impl<I: Interface> ComPtr<I> {
+    /// Take the ownership of the pointer, `None` for a failed call
+    fn from_raw(res: HRESULT, ptr: *mut I) -> Option<ComPtr<I>> {
+        if res != S_OK || ptr.is_null() {
+            return None;
+        }
+        Some(ComPtr(ptr))
+    }
+
+    fn unknown(&self) -> &IUnknown {
+        unsafe { &*(self.0 as *mut IUnknown) }
+    }
+
+    fn cast<J: Interface>(&self) -> Option<ComPtr<J>> {
+        let mut ptr = std::ptr::null_mut();
+        let res = unsafe { self.unknown().QueryInterface(&J::uuidof(), &mut ptr) };
+        ComPtr::from_raw(res, ptr as *mut J)
+    }
+}
+
+impl<I: Interface> std::ops::Deref for ComPtr<I> {
+    type Target = I;
+    fn deref(&self) -> &I {
+        unsafe { &*self.0 }
+    }
+}
+
+impl<I: Interface> Drop for ComPtr<I> {
+    fn drop(&mut self) {
+        unsafe { self.unknown().Release() };
+    }
+}
+
+/// Handler of the `Activated` event, posts the activation to the window
+#[repr(C)]
+struct ActivatedHandler {
+    vtbl: *const ActivatedHandlerVtbl,
+    refs: AtomicU32,
+    hwnd: usize,
+}
+
+#[repr(C)]
+struct ActivatedHandlerVtbl {
+    parent: IUnknownVtbl,
+    invoke: unsafe extern "system" fn(
+        this: *mut ActivatedHandler,
+        sender: *mut IInspectable,
+        args: *mut IInspectable,
+    ) -> HRESULT,
+}
+
+static ACTIVATED_HANDLER_VTBL: ActivatedHandlerVtbl = ActivatedHandlerVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: ActivatedHandler::query_interface,
+        AddRef: ActivatedHandler::add_ref,
+        Release: ActivatedHandler::release,
+    },
+    invoke: ActivatedHandler::invoke,
+};
+
+impl ActivatedHandler {
+    /// New handler with one reference
+    fn create(hwnd: HWND) -> *mut IUnknown {
+        Box::into_raw(Box::new(ActivatedHandler {
+            vtbl: &ACTIVATED_HANDLER_VTBL,
+            refs: AtomicU32::new(1),
+            hwnd: hwnd as usize,
+        })) as *mut IUnknown
+    }
+
+    unsafe extern "system" fn query_interface(
+        this: *mut IUnknown,
+        riid: REFIID,
+        object: *mut *mut winapi::ctypes::c_void,
+    ) -> HRESULT {
+        if object.is_null() {
+            return E_POINTER;
+        }
+        let riid = &*riid;
+        if IsEqualGUID(riid, &IUnknown::uuidof())
+            || IsEqualGUID(riid, &IID_AGILE_OBJECT)
+            || IsEqualGUID(riid, &IID_ACTIVATED_HANDLER)
+        {
+            ActivatedHandler::add_ref(this);
+            *object = this as _;
+            return S_OK;
+        }
+        *object = std::ptr::null_mut();
+        E_NOINTERFACE
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+        let this = &*(this as *mut ActivatedHandler);
+        this.refs.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+        let refs = (*(this as *mut ActivatedHandler))
+            .refs
+            .fetch_sub(1, Ordering::Release)
+            - 1;
+        if refs == 0 {
+            std::sync::atomic::fence(Ordering::Acquire);
+            drop(Box::from_raw(this as *mut ActivatedHandler));
+        }
+        refs
+    }
+
+    unsafe extern "system" fn invoke(
+        this: *mut ActivatedHandler,
+        _sender: *mut IInspectable,
+        args: *mut IInspectable,
+    ) -> HRESULT {
+        let this = &*this;
+        let mut arguments = std::ptr::null_mut();
+        if !args.is_null() {
+            let args = &*(args as *mut IUnknown);
+            let mut activated = std::ptr::null_mut();
+            if args.QueryInterface(&IToastActivatedEventArgs::uuidof(), &mut activated) == S_OK {
+                let activated = &*(activated as *mut IToastActivatedEventArgs);
+                if activated.get_Arguments(&mut arguments) != S_OK {
+                    arguments = std::ptr::null_mut();
+                }
+                activated.Release();
+            }
+        }
+        let action = action_index(&hstring_to_string(arguments));
+        winstring::WindowsDeleteString(arguments);
+
+        // Window may be gone already, then posting fails
+        winuser::PostMessageW(this.hwnd as HWND, msgs::WM_USER_TOAST, action, 0);
+        S_OK
+    }
+}
+
+fn hstring_to_string(hstring: HSTRING) -> String {
+    let mut len = 0;
+    let ptr = unsafe { winstring::WindowsGetStringRawBuffer(hstring, &mut len) };
+    if ptr.is_null() || len == 0 {
+        return String::new();
+    }
+    String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
+}
+
+/// `wparam` of the activation from the arguments of the toast XML
+fn action_index(arguments: &str) -> usize {
+    arguments
+        .strip_prefix("action=")
+        .and_then(|index| index.parse::<usize>().ok())
+        .map_or(0, |index| index + 1)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Toast content with a button for each action
+fn toast_xml(title: &str, message: &str, actions: &[&str], silent: bool) -> String {
+    let mut xml = format!(
+        "<toast><visual><binding template=\"ToastGeneric\"><text>{}</text><text>{}</text></binding></visual>",
+        escape_xml(title),
+        escape_xml(message)
+    );
+    if !actions.is_empty() {
+        xml.push_str("<actions>");
+        for (index, label) in actions.iter().enumerate() {
+            xml.push_str(&format!(
+                "<action content=\"{}\" arguments=\"action={}\" activationType=\"foreground\"/>",
+                escape_xml(label),
+                index
+            ));
+        }
+        xml.push_str("</actions>");
+    }
+    if silent {
+        xml.push_str("<audio silent=\"true\"/>");
+    }
+    xml.push_str("</toast>");
+    xml
+}
+
+/// Windows Runtime initialization of the thread
+struct RoInit {
+    uninitialize: bool,
+}
+
+impl RoInit {
+    fn new() -> Option<RoInit> {
+        let res = unsafe { roapi::RoInitialize(roapi::RO_INIT_SINGLETHREADED) };
+
+        // Thread may already be initialized with another apartment type
+        if res < 0 && res != RPC_E_CHANGED_MODE {
+            return None;
+        }
+        Some(RoInit {
+            uninitialize: res >= 0,
+        })
+    }
+}
+
+impl Drop for RoInit {
+    fn drop(&mut self) {
+        if self.uninitialize {
+            unsafe { roapi::RoUninitialize() };
+        }
+    }
+}
+
+/// Toast notifier of the application
+///
+/// Unpackaged applications need a Start menu shortcut with the application
+/// user model id, otherwise creating or showing the toasts fails and the
+/// balloon is used instead.
+pub struct Toasts {
+    // Interfaces are released before uninitializing, in the field order
+    current: Option<ComPtr<IToastNotification>>,
+    notifier: ComPtr<IToastNotifier>,
+    _init: RoInit,
+}
+
+impl Toasts {
+    /// Notifier for the application id, `None` if toasts aren't available
+    pub fn new(app_id: &str) -> Option<Toasts> {
+        let init = RoInit::new()?;
+        Some(Toasts {
+            current: None,
+            notifier: Toasts::notifier(app_id)?,
+            _init: init,
+        })
+    }
+
+    fn notifier(app_id: &str) -> Option<ComPtr<IToastNotifier>> {
+        let class = HString::new("Windows.UI.Notifications.ToastNotificationManager")?;
+        let app_id = HString::new(app_id)?;
+        let mut statics = std::ptr::null_mut();
+        let res = unsafe {
+            roapi::RoGetActivationFactory(
+                class.0,
+                &IToastNotificationManagerStatics::uuidof(),
+                &mut statics,
+            )
+        };
+        let statics = ComPtr::from_raw(res, statics as *mut IToastNotificationManagerStatics)?;
+        let mut notifier = std::ptr::null_mut();
+        let res = unsafe { statics.CreateToastNotifierWithId(app_id.0, &mut notifier) };
+        ComPtr::from_raw(res, notifier)
+    }
+
+    /// Show the toast, button clicks are posted to the window
+    pub fn show(
+        &mut self,
+        hwnd: HWND,
+        title: &str,
+        message: &str,
+        actions: &[&str],
+        silent: bool,
+    ) -> bool {
+        let notification = match Toasts::notification(&toast_xml(title, message, actions, silent)) {
+            Some(notification) => notification,
+            None => return false,
+        };
+        let handler = ActivatedHandler::create(hwnd);
+        let mut token = 0;
+        let res = unsafe { notification.add_Activated(handler, &mut token) };
+        unsafe { (*handler).Release() };
+        if res != S_OK {
+            return false;
+        }
+        if unsafe { self.notifier.Show(notification.0) } != S_OK {
+            return false;
+        }
+        self.current = Some(notification);
+        true
+    }
+
+    fn notification(xml: &str) -> Option<ComPtr<IToastNotification>> {
+        let document_class = HString::new("Windows.Data.Xml.Dom.XmlDocument")?;
+        let mut document = std::ptr::null_mut();
+        let res = unsafe { roapi::RoActivateInstance(document_class.0, &mut document) };
+        let document = ComPtr::from_raw(res, document)?;
+        let xml = HString::new(xml)?;
+        let io = document.cast::<IXmlDocumentIO>()?;
+        if unsafe { io.LoadXml(xml.0) } != S_OK {
+            return None;
+        }
+        let document = document.cast::<IXmlDocument>()?;
+
+        let class = HString::new("Windows.UI.Notifications.ToastNotification")?;
+        let mut factory = std::ptr::null_mut();
+        let res = unsafe {
+            roapi::RoGetActivationFactory(
+                class.0,
+                &IToastNotificationFactory::uuidof(),
+                &mut factory,
+            )
+        };
+        let factory = ComPtr::from_raw(res, factory as *mut IToastNotificationFactory)?;
+        let mut notification = std::ptr::null_mut();
+        let res = unsafe { factory.CreateToastNotification(document.0, &mut notification) };
+        ComPtr::from_raw(res, notification)
+    }
+
+    /// Hide the latest toast, `false` if there is none
+    pub fn hide(&mut self) -> bool {
+        match self.current.take() {
+            Some(notification) => unsafe { self.notifier.Hide(notification.0) == S_OK },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toast_xml() {
+        assert_eq!(
+            toast_xml("Title & <more>", "Message", &["Open \"it\"", "Later"], true),
+            "<toast><visual><binding template=\"ToastGeneric\"><text>Title &amp; &lt;more&gt;</text>\
+             <text>Message</text></binding></visual><actions>\
+             <action content=\"Open &quot;it&quot;\" arguments=\"action=0\" activationType=\"foreground\"/>\
+             <action content=\"Later\" arguments=\"action=1\" activationType=\"foreground\"/>\
+             </actions><audio silent=\"true\"/></toast>"
+        );
+        assert!(!toast_xml("Title", "Message", &[], false).contains("<actions>"));
+    }
+
+    #[test]
+    fn test_action_index() {
+        assert_eq!(action_index(""), 0);
+        assert_eq!(action_index("action=0"), 1);
+        assert_eq!(action_index("action=2"), 3);
+        assert_eq!(action_index("other"), 0);
+    }
+}
@@ -7,12 +7,37 @@ pub fn wchar(string: &str) -> Vec<u16> {
 
 /// Copies string to WCHAR array, ensuring that array has null terminator
 ///
-/// Use this if winapi struct of certain size requires WCHAR array
+/// Use this if winapi struct of certain size requires WCHAR array. Too long
+/// strings are truncated on a character boundary, surrogate pairs are never
+/// split.
 pub fn wchar_array(string: &str, dst: &mut [u16]) {
+    copy_truncated(string, dst, false);
+}
+
+/// Like `wchar_array`, but a truncated string ends with an ellipsis
+pub fn wchar_array_ellipsis(string: &str, dst: &mut [u16]) {
+    copy_truncated(string, dst, true);
+}
+
+fn copy_truncated(string: &str, dst: &mut [u16], ellipsis: bool) {
+    let max = dst.len() - 1;
     let mut s = string.encode_utf16().collect::<Vec<_>>();
 
     // Truncate utf16 array to fit in the buffer with null terminator
-    s.truncate(dst.len() - 1);
+    if s.len() > max {
+        let limit = if ellipsis { max.saturating_sub(1) } else { max };
+        let mut len = 0;
+        for c in string.chars() {
+            if len + c.len_utf16() > limit {
+                break;
+            }
+            len += c.len_utf16();
+        }
+        s.truncate(len);
+        if ellipsis && max > 0 {
+            s.push('\u{2026}' as u16);
+        }
+    }
 
     dst[..s.len()].copy_from_slice(s.as_slice());
 
@@ -47,4 +72,32 @@ mod tests {
         wchar_array("", dst.as_mut());
         assert_eq!(dst, [0, 99, 99, 99, 99]);
     }
+
+    /// Ensure that non-latin strings are encoded as UTF-16
+    #[test]
+    fn test_wchar_unicode() {
+        let mut dst: [u16; 8] = [99; 8];
+        wchar_array("Äö日本👀", dst.as_mut());
+        assert_eq!(dst, [0xC4, 0xF6, 0x65E5, 0x672C, 0xD83D, 0xDC40, 0, 99]);
+    }
+
+    /// Ensure that surrogate pairs are not split when truncating
+    #[test]
+    fn test_wchar_truncate_surrogate() {
+        let mut dst: [u16; 4] = [99; 4];
+        wchar_array("ab👀", dst.as_mut());
+        assert_eq!(dst, [0x61, 0x62, 0, 99]);
+    }
+
+    /// Ensure that truncated strings end with an ellipsis
+    #[test]
+    fn test_wchar_ellipsis() {
+        let mut dst: [u16; 5] = [99; 5];
+        wchar_array_ellipsis("HELLO WORLD", dst.as_mut());
+        assert_eq!(dst, [72, 69, 76, 0x2026, 0]);
+
+        let mut dst: [u16; 5] = [99; 5];
+        wchar_array_ellipsis("HELL", dst.as_mut());
+        assert_eq!(dst, [72, 69, 76, 76, 0]);
+    }
 }
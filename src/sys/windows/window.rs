@@ -1,34 +1,119 @@
-use super::{msgs, notifyicon::NotifyIcon, MenuSys};
+use super::{msgs, notifyicon::NotifyIcon, wchar::wchar, MenuSys};
 use winapi::shared::{
     basetsd::{DWORD_PTR, UINT_PTR},
-    minwindef::{HIWORD, LOWORD, LPARAM, LPVOID, LRESULT, UINT, WPARAM},
-    windef::{HBRUSH, HICON, HMENU, HWND, POINT},
+    minwindef::{FALSE, HINSTANCE, HIWORD, LOWORD, LPARAM, LPVOID, LRESULT, TRUE, UINT, WPARAM},
+    windef::{HBRUSH, HICON, HMENU, HWND, POINT, RECT},
+    winerror,
 };
-use winapi::um::libloaderapi::GetModuleHandleA;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::libloaderapi;
 use winapi::um::winuser;
 use winapi::um::winuser::{CreateWindowExA, DefWindowProcA, RegisterClassA};
 
-use crate::{trayiconsender::TrayIconSender, Error, Icon, MenuBuilder, TrayIconBase};
+use crate::{
+    trayeventhandler::{Callback, EventHandler, MenuInitHook, RawMessageHook},
+    trayiconsender::TrayIconSender,
+    ClickInfo, Error, Icon, MenuBuilder, MenuInit, Modifiers, MouseButton, NotificationOptions,
+    Point, Rect, TrayEventHandler, TrayIconBase, TrayIconBuilder, TrayIconUpdate,
+};
 use std::fmt::Debug;
-use winapi::um::commctrl;
+use winapi::um::{commctrl, shellapi};
 
 /// Tray Icon WINAPI Window
 ///
 /// In Windows the Tray Icon requires a window for message pump, it's not shown.
-#[derive(Debug)]
 pub struct TrayIconWindow<T>
 where
     T: PartialEq + Clone + 'static,
 {
     hwnd: HWND,
-    sender: TrayIconSender<T>,
+    class_name: String,
+    sender: Option<TrayIconSender<T>>,
+    handler: Option<EventHandler<T>>,
+    on_raw_message: Option<RawMessageHook>,
+    on_menu_init: Option<MenuInitHook<T>>,
     menu: Option<MenuSys<T>>,
+    dynamic_menu: Option<Callback<MenuBuilder<T>>>,
+    dynamic_tooltip: Option<Callback<String>>,
+    dynamic_tooltip_text: Option<String>,
     notify_icon: NotifyIcon,
     on_click: Option<T>,
     on_double_click: Option<T>,
     on_right_click: Option<T>,
+    on_ready: Option<T>,
+    on_high_contrast_changed: Option<T>,
+    on_session_end_query: Option<T>,
+    on_session_end: Option<T>,
+    on_taskbar_moved: Option<T>,
+    on_notification_click: Option<T>,
+    on_notification_timeout: Option<T>,
+    on_notification_hidden: Option<T>,
+    #[cfg(feature = "toast")]
+    toasts: Option<super::toast::Toasts>,
+    #[cfg(feature = "toast")]
+    notification_actions: Vec<(String, T)>,
+    on_hover: Option<T>,
+    on_unknown_menu_id: Option<fn(usize) -> T>,
+    on_menu_cancelled: Option<T>,
+    on_menu_closed: Option<T>,
+    on_click_info: Option<fn(ClickInfo) -> T>,
+    delay_click: bool,
+    double_click_default_item: bool,
+    auto_show_menu: bool,
+    menu_on_left_click: bool,
+    quit_on_close: bool,
+    shutdown_blocked: bool,
+    skip_click: bool,
+    hovering: bool,
+    delayed_click: Option<ClickInfo>,
+    interactive: bool,
+    icon: Option<Icon>,
+    active_icon: Option<Icon>,
+    pending_icon: Option<Icon>,
+    icon_timer: bool,
+    destroyed: bool,
+    removed: bool,
+    wm_taskbarcreated: UINT,
 }
 
+/// Window class of the tray icon windows
+const WND_CLASS_NAME: &str = "TrayIconCls\0";
+
+/// Registering and unregistering the window class
+static CLASS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Module of this code, e.g. a DLL the crate is linked into
+///
+/// Window class is registered to the module with the window procedure, so it
+/// can be unregistered before the DLL is unloaded.
+fn module_handle() -> HINSTANCE {
+    let mut module = std::ptr::null_mut();
+    unsafe {
+        libloaderapi::GetModuleHandleExA(
+            libloaderapi::GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS
+                | libloaderapi::GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            module_handle as *const () as _,
+            &mut module,
+        );
+    }
+    module
+}
+
+/// Timer identifier for coalesced icon updates
+const ICON_TIMER_ID: UINT_PTR = 1;
+
+/// Minimum interval of coalesced icon updates in milliseconds
+const ICON_TIMER_INTERVAL: UINT = 100;
+
+/// Timer identifier for delayed click event
+const CLICK_TIMER_ID: UINT_PTR = 2;
+
+/// Timer identifier for the end of hovering
+const HOVER_TIMER_ID: UINT_PTR = 3;
+
+/// Time without mouse moves after which hovering ends in milliseconds
+const HOVER_TIMER_INTERVAL: UINT = 1000;
+
 unsafe impl<T> Send for TrayIconWindow<T> where T: PartialEq + Clone {}
 unsafe impl<T> Sync for TrayIconWindow<T> where T: PartialEq + Clone {}
 
@@ -36,21 +121,33 @@ impl<T> TrayIconWindow<T>
 where
     T: PartialEq + Clone + 'static,
 {
-    #[allow(clippy::too_many_arguments)]
-    pub(crate) fn new(
-        sender: TrayIconSender<T>,
-        menu: Option<MenuSys<T>>,
-        notify_icon: NotifyIcon,
-        on_click: Option<T>,
-        on_double_click: Option<T>,
-        on_right_click: Option<T>,
-    ) -> Result<Box<TrayIconWindow<T>>, Error>
+    /// Create the window and the notify icon from the builder
+    ///
+    /// Icon and tooltip are part of the notify icon data before it's added, so
+    /// the icon never shows up without them.
+    pub(crate) fn new(builder: &TrayIconBuilder<T>) -> Result<Box<TrayIconWindow<T>>, Error>
     where
         T: PartialEq + Clone + 'static,
     {
+        // Handler can be used without a sender, and display only icons need
+        // neither
+        if builder.sender.is_none() && builder.handler.is_none() && builder.has_events() {
+            return Err(Error::SenderMissing);
+        }
+        if builder.menu_on_left_click && builder.on_click.is_some() {
+            return Err(Error::ConflictingClickEvents);
+        }
+        let notify_icon = NotifyIcon::new(builder)?;
+        let menu = match &builder.menu {
+            Some(menu) => Some(menu.build()?),
+            None => None,
+        };
         unsafe {
-            let hinstance = GetModuleHandleA(0 as _);
-            let wnd_class_name = "TrayIconCls\0";
+            let hinstance = module_handle();
+            let wnd_class_name = match &builder.window_class {
+                Some(name) => format!("{}\0", name),
+                None => WND_CLASS_NAME.to_string(),
+            };
             let wnd_class = winuser::WNDCLASSA {
                 style: 0,
                 lpfnWndProc: Some(TrayIconWindow::<T>::winproc),
@@ -63,17 +160,71 @@ where
                 hbrBackground: 0 as HBRUSH,
                 lpszMenuName: 0 as _,
             };
-            RegisterClassA(&wnd_class);
+            // Class is registered once per process, it's already registered
+            // for the second tray icon or after a failed creation. Window
+            // procedure doesn't depend on `T` so any registration works. Lock
+            // keeps the last window from unregistering it before this window
+            // is created.
+            let class_lock = CLASS_LOCK.lock();
+            if RegisterClassA(&wnd_class) == 0
+                && GetLastError() != winerror::ERROR_CLASS_ALREADY_EXISTS
+            {
+                return Err(Error::OsError);
+            }
 
             // Create window in a memory location that doesn't change
             let mut window = Box::new(TrayIconWindow {
                 hwnd: 0 as HWND,
+                class_name: wnd_class_name.clone(),
                 notify_icon,
                 menu,
-                on_click,
-                on_right_click,
-                on_double_click,
-                sender,
+                dynamic_menu: builder.dynamic_menu.clone(),
+                dynamic_tooltip: builder.dynamic_tooltip.clone(),
+                dynamic_tooltip_text: None,
+                on_click: builder.on_click.clone(),
+                on_right_click: builder.on_right_click.clone(),
+                on_double_click: builder.on_double_click.clone(),
+                on_ready: builder.on_ready.clone(),
+                on_high_contrast_changed: builder.on_high_contrast_changed.clone(),
+                on_session_end_query: builder.on_session_end_query.clone(),
+                on_session_end: builder.on_session_end.clone(),
+                on_taskbar_moved: builder.on_taskbar_moved.clone(),
+                on_notification_click: builder.on_notification_click.clone(),
+                on_notification_timeout: builder.on_notification_timeout.clone(),
+                on_notification_hidden: builder.on_notification_hidden.clone(),
+                #[cfg(feature = "toast")]
+                toasts: builder
+                    .toast_app_id
+                    .as_deref()
+                    .and_then(super::toast::Toasts::new),
+                #[cfg(feature = "toast")]
+                notification_actions: builder.notification_actions.clone(),
+                on_hover: builder.on_hover.clone(),
+                on_unknown_menu_id: builder.on_unknown_menu_id,
+                on_menu_cancelled: builder.on_menu_cancelled.clone(),
+                on_menu_closed: builder.on_menu_closed.clone(),
+                on_click_info: builder.on_click_info,
+                delay_click: builder.delay_click,
+                double_click_default_item: builder.double_click_default_item,
+                auto_show_menu: builder.auto_show_menu,
+                menu_on_left_click: builder.menu_on_left_click,
+                quit_on_close: builder.quit_on_close,
+                shutdown_blocked: false,
+                skip_click: false,
+                hovering: false,
+                delayed_click: None,
+                interactive: true,
+                sender: builder.sender.clone(),
+                handler: builder.handler.clone(),
+                on_raw_message: builder.on_raw_message.clone(),
+                on_menu_init: builder.on_menu_init.clone(),
+                icon: builder.icon.as_ref().ok().cloned(),
+                active_icon: builder.active_icon.clone(),
+                pending_icon: None,
+                icon_timer: false,
+                destroyed: false,
+                removed: false,
+                wm_taskbarcreated: winuser::RegisterWindowMessageA("TaskbarCreated\0".as_ptr() as _),
             });
             // Take the window memory location and pass it to wndproc and
             // subproc
@@ -91,11 +242,16 @@ where
                 winuser::CW_USEDEFAULT,
                 winuser::CW_USEDEFAULT,
                 winuser::CW_USEDEFAULT,
-                0 as _,
+                if builder.message_only {
+                    winuser::HWND_MESSAGE
+                } else {
+                    0 as _
+                },
                 0 as HMENU,
                 hinstance,
                 ptr as *mut _ as LPVOID,
             ) as u32;
+            drop(class_lock);
             if hwnd == 0 {
                 return Err(Error::OsError);
             }
@@ -113,6 +269,237 @@ where
         }
     }
 
+    #[cfg(test)]
+    pub(crate) fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    #[cfg(test)]
+    pub(crate) fn icon_id(&self) -> u32 {
+        self.notify_icon.id()
+    }
+
+    /// Send the event, if there is a sender
+    fn send(&self, e: &T) {
+        if let Some(sender) = &self.sender {
+            sender.send(e);
+        }
+    }
+
+    /// Call the handler, if there is one
+    fn call_handler<F>(&self, f: F)
+    where
+        F: FnOnce(&mut dyn TrayEventHandler<T>),
+    {
+        if let Some(handler) = &self.handler {
+            handler.call(f);
+        }
+    }
+
+    /// Send the click event and call the handler
+    fn click(&mut self, info: ClickInfo) {
+        // Menu takes the left click
+        if self.menu_on_left_click && (self.menu.is_some() || self.dynamic_menu.is_some()) {
+            let _ = self.track_menu(info.position.x, info.position.y);
+            return;
+        }
+        if let Some(e) = self.on_click.as_ref() {
+            self.send(e);
+        }
+        self.click_info(info);
+        self.call_handler(|h| h.on_left_click());
+    }
+
+    /// Send the click info event
+    fn click_info(&self, info: ClickInfo) {
+        if let Some(f) = self.on_click_info {
+            self.send(&f(info));
+        }
+    }
+
+    /// Details of the click, position is in `wparam` with version 4
+    fn get_click_info(&self, button: MouseButton, double: bool, wparam: WPARAM) -> ClickInfo {
+        let position = if self.notify_icon.is_version_4() {
+            Point {
+                x: LOWORD(wparam as u32) as i16 as i32,
+                y: HIWORD(wparam as u32) as i16 as i32,
+            }
+        } else {
+            let mut pos = POINT { x: 0, y: 0 };
+            unsafe { winuser::GetCursorPos(&mut pos as _) };
+            pos.into()
+        };
+        // Notifications from the shell aren't input messages, so the key
+        // state of this thread isn't updated and the current state is used
+        let is_down = |key| unsafe { winuser::GetAsyncKeyState(key) } < 0;
+        ClickInfo {
+            button,
+            double,
+            modifiers: Modifiers {
+                shift: is_down(winuser::VK_SHIFT),
+                control: is_down(winuser::VK_CONTROL),
+                alt: is_down(winuser::VK_MENU),
+            },
+            position,
+        }
+    }
+
+    /// Add the notify icon, and send the ready event if it succeeds
+    fn add_notify_icon(&mut self, hwnd: HWND) -> Result<(), Error> {
+        self.notify_icon.add(hwnd)?;
+        if let Some(e) = self.on_ready.as_ref() {
+            self.send(e);
+        }
+        Ok(())
+    }
+
+    /// Register or unregister the window for appbar notifications
+    ///
+    /// Window doesn't reserve any screen space, it only gets notified when
+    /// e.g. the taskbar moves.
+    fn appbar_message(&self, message: u32) {
+        let mut data: shellapi::APPBARDATA = unsafe { std::mem::zeroed() };
+        data.cbSize = std::mem::size_of::<shellapi::APPBARDATA>() as u32;
+        data.hWnd = self.hwnd;
+        data.uCallbackMessage = msgs::WM_USER_APPBAR;
+        unsafe { shellapi::SHAppBarMessage(message, &mut data) };
+    }
+
+    /// Show the menu at the position, returns the event of the selected item
+    ///
+    /// Selected item is dispatched when the menu closes, a cancelled menu
+    /// sends only the cancel event. Closed event is sent after either.
+    ///
+    /// Menu closes on click outside only if the window is foreground, so the
+    /// window takes the foreground only for the menu. If setting the
+    /// foreground fails the menu is still shown, it can be closed by selecting
+    /// an item or pressing escape.
+    ///
+    /// Menu is aligned so that it opens away from the closest edges of the
+    /// monitor of the position, e.g. with bottom taskbar it opens above the
+    /// position.
+    ///
+    /// Dynamic menu is rebuilt before showing it. Active icon is shown while
+    /// the menu is open, tracking is synchronous so it's always restored.
+    fn track_menu(&mut self, x: i32, y: i32) -> Result<Option<T>, Error> {
+        if let Some(dynamic_menu) = &self.dynamic_menu {
+            let menu = dynamic_menu.call();
+            self.set_menu(&menu)?;
+        }
+        if self.menu.is_none() {
+            return Err(Error::MenuMissing);
+        }
+        if let Some(icon) = &self.active_icon {
+            self.notify_icon.set_icon(&icon.sys);
+        }
+        let menu = self.menu.as_ref().ok_or(Error::MenuMissing)?;
+        let mut flags = winuser::TPM_RIGHTBUTTON;
+        let monitor = monitor_rect(POINT { x, y });
+        if x > (monitor.left + monitor.right) / 2 {
+            flags |= winuser::TPM_RIGHTALIGN;
+        }
+        if y > (monitor.top + monitor.bottom) / 2 {
+            flags |= winuser::TPM_BOTTOMALIGN;
+        }
+        let previous = unsafe { winuser::GetForegroundWindow() };
+        if previous != self.hwnd {
+            unsafe {
+                if winuser::SetForegroundWindow(self.hwnd) == 0 {
+                    // Foreground lock may prevent it, retry once after the
+                    // window is brought to top
+                    winuser::BringWindowToTop(self.hwnd);
+                    winuser::SetForegroundWindow(self.hwnd);
+                }
+            }
+        }
+        let selected = match menu.menu.track(self.hwnd, x, y, flags) {
+            Some(identifier) => self.menu_command(identifier),
+            None => {
+                self.restore_foreground(previous);
+                if let Some(e) = self.on_menu_cancelled.as_ref() {
+                    self.send(e);
+                }
+                None
+            }
+        };
+        if self.active_icon.is_some() {
+            if let Some(icon) = &self.icon {
+                self.notify_icon.set_icon(&icon.sys);
+            }
+        }
+        if let Some(e) = self.on_menu_closed.as_ref() {
+            self.send(e);
+        }
+        self.call_handler(|h| h.on_menu_closed());
+        Ok(selected)
+    }
+
+    /// Give the foreground back after a cancelled menu
+    ///
+    /// Only if the hidden window still has it, e.g. the menu was closed with
+    /// escape. Clicking outside the menu already activated another window.
+    /// After a selection the foreground is kept, so that the application may
+    /// activate its own windows in response.
+    fn restore_foreground(&self, previous: HWND) {
+        unsafe {
+            if !previous.is_null()
+                && previous != self.hwnd
+                && winuser::GetForegroundWindow() == self.hwnd
+                && winuser::IsWindow(previous) != 0
+            {
+                winuser::SetForegroundWindow(previous);
+            }
+        }
+    }
+
+    /// Update the dynamic tooltip if it has changed
+    ///
+    /// Standard tooltip reads the text when it's shown, so updating it while
+    /// hovering keeps it current.
+    fn update_dynamic_tooltip(&mut self) {
+        let tooltip = match &self.dynamic_tooltip {
+            Some(dynamic_tooltip) => dynamic_tooltip.call(),
+            None => return,
+        };
+        if self.dynamic_tooltip_text.as_ref() != Some(&tooltip) {
+            self.notify_icon.set_tooltip(&tooltip);
+            self.dynamic_tooltip_text = Some(tooltip);
+        }
+    }
+
+    /// Send the hover event on the first mouse move
+    ///
+    /// Moves arrive frequently, each one restarts the timer that ends the
+    /// hovering.
+    fn hover(&mut self) {
+        let e = match &self.on_hover {
+            Some(e) => e,
+            None => return,
+        };
+        if !self.hovering {
+            self.hovering = true;
+            self.send(e);
+        }
+        unsafe { winuser::SetTimer(self.hwnd, HOVER_TIMER_ID, HOVER_TIMER_INTERVAL, None) };
+    }
+
+    /// Send the event of the menu item
+    ///
+    /// Identifier 0 is never a menu item, it means nothing was selected.
+    fn menu_command(&self, identifier: usize) -> Option<T> {
+        if identifier == 0 {
+            return None;
+        }
+        let event = self
+            .menu
+            .as_ref()
+            .and_then(|v| v.ids.get(&identifier).cloned())
+            .or_else(|| self.on_unknown_menu_id.map(|f| f(identifier)))?;
+        self.send(&event);
+        self.call_handler(|h| h.on_menu(&event));
+        Some(event)
+    }
+
     // This serves as a conduit for actual winproc in the subproc
     pub unsafe extern "system" fn winproc(
         hwnd: HWND,
@@ -133,7 +520,32 @@ where
     }
 
     // Actual winproc
+    //
+    // Panics can't unwind out of the window procedure, e.g. from a panicking
+    // handler, and the process is aborted. The icon is removed first, since
+    // the shell would show it until the mouse moves over it.
     unsafe extern "system" fn subproc(
+        hwnd: HWND,
+        msg: UINT,
+        wparam: WPARAM,
+        lparam: LPARAM,
+        id: UINT_PTR,
+        data: DWORD_PTR,
+    ) -> LRESULT {
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            TrayIconWindow::<T>::handle_message(hwnd, msg, wparam, lparam, id, data)
+        }));
+        match res {
+            Ok(res) => res,
+            Err(_) => {
+                let window: &mut TrayIconWindow<T> = &mut *(data as *mut _);
+                window.notify_icon.remove();
+                std::process::abort();
+            }
+        }
+    }
+
+    unsafe fn handle_message(
         hwnd: HWND,
         msg: UINT,
         wparam: WPARAM,
@@ -141,23 +553,95 @@ where
         _id: UINT_PTR,
         data: DWORD_PTR,
     ) -> LRESULT {
-        static mut WM_TASKBARCREATED: u32 = u32::MAX;
         let window: &mut TrayIconWindow<T> = &mut *(data as *mut _);
+
+        // Messages arriving during teardown must not send events or touch the
+        // notify icon
+        if window.destroyed && msg != winuser::WM_NCDESTROY {
+            return commctrl::DefSubclassProc(hwnd, msg, wparam, lparam);
+        }
+
+        if let Some(hook) = window.on_raw_message.as_ref() {
+            if let Some(res) = hook.call(hwnd as usize, msg, wparam, lparam) {
+                return res;
+            }
+        }
+
+        let callback_message = window.notify_icon.callback_message();
         match msg {
             // Window was created
             msgs::WM_USER_CREATE => {
-                WM_TASKBARCREATED =
-                    winuser::RegisterWindowMessageA("TaskbarCreated\0".as_ptr() as _);
-                window.notify_icon.add(hwnd);
+                // Nothing to return the error to, the icon is added again if
+                // the taskbar is created later
+                if !window.removed {
+                    let _ = window.add_notify_icon(hwnd);
+                }
+                if window.on_taskbar_moved.is_some() {
+                    window.appbar_message(shellapi::ABM_NEW);
+                }
+            }
+
+            // Wake-up of a detached tray icon dispatched by a modal loop, the
+            // message loop handles it after the modal loop ends
+            msgs::WM_USER_WAKE => {}
+
+            // Appbar notifications
+            msgs::WM_USER_APPBAR => {
+                if wparam as u32 == shellapi::ABN_POSCHANGED {
+                    if let Some(e) = window.on_taskbar_moved.as_ref() {
+                        window.send(e);
+                    }
+                }
             }
 
+            // Toast or its button was clicked
+            #[cfg(feature = "toast")]
+            msgs::WM_USER_TOAST => {
+                if wparam == 0 {
+                    if let Some(e) = window.on_notification_click.as_ref() {
+                        window.send(e);
+                    }
+                    window.call_handler(|h| h.on_balloon_click());
+                } else if !window.interactive {
+                    // Actions are ignored like menu commands
+                } else if let Some((_, e)) = window.notification_actions.get(wparam - 1) {
+                    window.send(e);
+                }
+            }
+
+            // Menu commands are ignored while not interactive
+            winuser::WM_COMMAND | winuser::WM_MENUCOMMAND if !window.interactive => {}
+
             // Mouse events on the tray icon
-            msgs::WM_USER_TRAYICON => {
-                match lparam as u32 {
+            x if x == callback_message => {
+                // Version 4 has the icon id in the high word
+                let event = if window.notify_icon.is_version_4() {
+                    LOWORD(lparam as u32) as u32
+                } else {
+                    lparam as u32
+                };
+                match event {
+                    // Clicks are ignored while not interactive
+                    winuser::WM_LBUTTONUP | winuser::WM_RBUTTONUP | winuser::WM_LBUTTONDBLCLK
+                        if !window.interactive => {}
+
                     // Left click tray icon
                     winuser::WM_LBUTTONUP => {
-                        if let Some(e) = window.on_click.as_ref() {
-                            window.sender.send(e);
+                        let info = window.get_click_info(MouseButton::Left, false, wparam);
+                        if window.skip_click {
+                            // Button up of a double click
+                            window.skip_click = false;
+                        } else if window.delay_click {
+                            // Wait and see if this is a double click
+                            window.delayed_click = Some(info);
+                            winuser::SetTimer(
+                                hwnd,
+                                CLICK_TIMER_ID,
+                                winuser::GetDoubleClickTime(),
+                                None,
+                            );
+                        } else {
+                            window.click(info);
                         }
                     }
 
@@ -165,24 +649,72 @@ where
                     winuser::WM_RBUTTONUP => {
                         // Send right click event
                         if let Some(e) = window.on_right_click.as_ref() {
-                            window.sender.send(e);
+                            window.send(e);
                         }
+                        window.click_info(window.get_click_info(MouseButton::Right, false, wparam));
+                        window.call_handler(|h| h.on_right_click());
 
                         // Show menu, if it's there
-                        if let Some(menu) = &window.menu {
+                        if window.auto_show_menu {
                             let mut pos = POINT { x: 0, y: 0 };
                             winuser::GetCursorPos(&mut pos as _);
-                            winuser::SetForegroundWindow(hwnd);
-                            menu.menu.track(hwnd, pos.x, pos.y);
+                            let _ = window.track_menu(pos.x, pos.y);
                         }
                     }
 
                     // Double click tray icon
                     winuser::WM_LBUTTONDBLCLK => {
+                        if window.delay_click {
+                            winuser::KillTimer(hwnd, CLICK_TIMER_ID);
+                            window.delayed_click = None;
+                            window.skip_click = true;
+                        }
                         if let Some(e) = window.on_double_click.as_ref() {
-                            window.sender.send(e);
+                            window.send(e);
+                        }
+                        window.click_info(window.get_click_info(MouseButton::Left, true, wparam));
+                        window.call_handler(|h| h.on_double_click());
+
+                        // Select the default item
+                        if window.double_click_default_item {
+                            let default_id = window.menu.as_ref().and_then(|v| v.default_id);
+                            if let Some(identifier) = default_id {
+                                window.menu_command(identifier);
+                            }
                         }
                     }
+
+                    // Mouse over the icon, or the popup should open with version 4
+                    winuser::WM_MOUSEMOVE | shellapi::NIN_POPUPOPEN => {
+                        window.update_dynamic_tooltip();
+                        if event == winuser::WM_MOUSEMOVE {
+                            window.hover();
+                        }
+                    }
+
+                    // Balloon notification clicked
+                    shellapi::NIN_BALLOONUSERCLICK => {
+                        if let Some(e) = window.on_notification_click.as_ref() {
+                            window.send(e);
+                        }
+                        window.call_handler(|h| h.on_balloon_click());
+                    }
+
+                    // Balloon notification timed out or closed with the X
+                    shellapi::NIN_BALLOONTIMEOUT => {
+                        if let Some(e) = window.on_notification_timeout.as_ref() {
+                            window.send(e);
+                        }
+                        window.call_handler(|h| h.on_balloon_timeout());
+                    }
+
+                    // Balloon notification removed, e.g. the icon was hidden
+                    shellapi::NIN_BALLOONHIDE => {
+                        if let Some(e) = window.on_notification_hidden.as_ref() {
+                            window.send(e);
+                        }
+                        window.call_handler(|h| h.on_balloon_hidden());
+                    }
                     _ => {}
                 }
             }
@@ -196,22 +728,130 @@ where
 
                 // Menu command
                 if cmd == 0 {
-                    if let Some(v) = window.menu.as_ref() {
-                        if let Some(event) = v.ids.get(&(identifier as usize)) {
-                            window.sender.send(event);
-                        }
+                    window.menu_command(identifier as usize);
+                }
+            }
+
+            // Menu command by position, menus with `MNS_NOTIFYBYPOS` style
+            //
+            // https://docs.microsoft.com/en-us/windows/win32/menurc/wm-menucommand
+            winuser::WM_MENUCOMMAND => {
+                let identifier = winuser::GetMenuItemID(lparam as HMENU, wparam as i32);
+                if identifier != u32::MAX {
+                    window.menu_command(identifier as usize);
+                }
+            }
+
+            // Menu is about to open, submenus get this too but the root menu
+            // covers their items
+            //
+            // https://docs.microsoft.com/en-us/windows/win32/menurc/wm-initmenupopup
+            winuser::WM_INITMENUPOPUP => {
+                if let (Some(hook), Some(menu)) = (&window.on_menu_init, &window.menu) {
+                    if wparam as HMENU == menu.menu.hmenu {
+                        hook.call(&mut MenuInit { menu });
+                    }
+                }
+            }
+
+            // Apply the latest coalesced icon, or stop if nothing has changed
+            winuser::WM_TIMER if wparam == ICON_TIMER_ID => {
+                if let Some(icon) = window.pending_icon.take() {
+                    if window.notify_icon.set_icon(&icon.sys) {
+                        window.icon = Some(icon);
+                    }
+                } else {
+                    winuser::KillTimer(hwnd, ICON_TIMER_ID);
+                    window.icon_timer = false;
+                }
+            }
+
+            // DPI changed, icons sized by the DPI are loaded again
+            winuser::WM_DPICHANGED => {
+                let reloaded = window.icon.as_ref().and_then(|icon| icon.reload());
+                if let Some(Ok(icon)) = reloaded {
+                    if window.notify_icon.set_icon(&icon.sys) {
+                        window.icon = Some(icon);
+                    }
+                }
+
+                // Shown later, when the menu opens or the timer fires
+                for icon in [&mut window.active_icon, &mut window.pending_icon] {
+                    if let Some(Ok(reloaded)) = icon.as_ref().and_then(|icon| icon.reload()) {
+                        *icon = Some(reloaded);
+                    }
+                }
+            }
+
+            // No double click happened, send the delayed click
+            winuser::WM_TIMER if wparam == CLICK_TIMER_ID => {
+                winuser::KillTimer(hwnd, CLICK_TIMER_ID);
+                if let Some(info) = window.delayed_click.take() {
+                    if window.interactive {
+                        window.click(info);
+                    }
+                }
+            }
+
+            // Mouse hasn't moved over the icon for a while
+            winuser::WM_TIMER if wparam == HOVER_TIMER_ID => {
+                winuser::KillTimer(hwnd, HOVER_TIMER_ID);
+                window.hovering = false;
+            }
+
+            // System settings changed
+            winuser::WM_SETTINGCHANGE => {
+                if wparam as u32 == winuser::SPI_SETHIGHCONTRAST {
+                    if let Some(e) = window.on_high_contrast_changed.as_ref() {
+                        window.send(e);
+                    }
+                }
+                return commctrl::DefSubclassProc(hwnd, msg, wparam, lparam);
+            }
+
+            // Session is about to end, allow it unless blocked
+            winuser::WM_QUERYENDSESSION => {
+                if let Some(e) = window.on_session_end_query.as_ref() {
+                    window.send(e);
+                }
+                return (if window.shutdown_blocked { FALSE } else { TRUE }) as LRESULT;
+            }
+
+            // Session is ending, process may be terminated after this, or the
+            // session end was cancelled when wparam is zero
+            winuser::WM_ENDSESSION => {
+                if wparam != 0 {
+                    if let Some(e) = window.on_session_end.as_ref() {
+                        window.send(e);
                     }
+                    window.notify_icon.remove();
                 }
             }
 
             // Destroy
             winuser::WM_DESTROY => {
+                window.destroyed = true;
                 window.notify_icon.remove();
+                if window.on_taskbar_moved.is_some() {
+                    window.appbar_message(shellapi::ABM_REMOVE);
+                }
+                if window.quit_on_close {
+                    winuser::PostQuitMessage(0);
+                }
+            }
+
+            // Last message of the window, the window data must not be used
+            // after this so the subclass is removed
+            winuser::WM_NCDESTROY => {
+                commctrl::RemoveWindowSubclass(hwnd, Some(TrayIconWindow::<T>::subproc), 0);
+                return commctrl::DefSubclassProc(hwnd, msg, wparam, lparam);
             }
 
             // TaskbarCreated
-            x if x == WM_TASKBARCREATED => {
-                window.notify_icon.add(hwnd);
+            x if x != 0 && x == window.wm_taskbarcreated => {
+                if !window.removed {
+                    let _ = window.add_notify_icon(hwnd);
+                }
             }
 
             _ => {
@@ -222,23 +862,334 @@ where
     }
 }
 
+/// Point next to the icon rectangle, on the side facing away from the taskbar
+///
+/// The taskbar is assumed to be on the edge of the icon's monitor closest to
+/// the icon, so per-monitor taskbars work too.
+fn menu_position(rect: &RECT) -> POINT {
+    let center = POINT {
+        x: (rect.left + rect.right) / 2,
+        y: (rect.top + rect.bottom) / 2,
+    };
+    let monitor = monitor_rect(center);
+    let edges = [
+        (
+            monitor.bottom - rect.bottom,
+            POINT {
+                x: center.x,
+                y: rect.top,
+            },
+        ),
+        (
+            rect.top - monitor.top,
+            POINT {
+                x: center.x,
+                y: rect.bottom,
+            },
+        ),
+        (
+            rect.left - monitor.left,
+            POINT {
+                x: rect.right,
+                y: center.y,
+            },
+        ),
+        (
+            monitor.right - rect.right,
+            POINT {
+                x: rect.left,
+                y: center.y,
+            },
+        ),
+    ];
+    edges
+        .iter()
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, pos)| *pos)
+        .unwrap_or(center)
+}
+
+/// Rectangle of the monitor nearest to the point
+///
+/// Falls back to the primary screen if the monitor info isn't available.
+fn monitor_rect(point: POINT) -> RECT {
+    unsafe {
+        let monitor = winuser::MonitorFromPoint(point, winuser::MONITOR_DEFAULTTONEAREST);
+        let mut info: winuser::MONITORINFO = std::mem::zeroed();
+        info.cbSize = std::mem::size_of::<winuser::MONITORINFO>() as u32;
+        if winuser::GetMonitorInfoW(monitor, &mut info) != 0 {
+            info.rcMonitor
+        } else {
+            RECT {
+                left: 0,
+                top: 0,
+                right: winuser::GetSystemMetrics(winuser::SM_CXSCREEN),
+                bottom: winuser::GetSystemMetrics(winuser::SM_CYSCREEN),
+            }
+        }
+    }
+}
+
 impl<T> TrayIconBase<T> for TrayIconWindow<T>
 where
     T: PartialEq + Clone + 'static,
 {
     /// Set the tooltip
     fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
+        self.dynamic_tooltip_text = None;
         if !self.notify_icon.set_tooltip(tooltip) {
             return Err(Error::OsError);
         }
         Ok(())
     }
 
+    /// Apply the changes of the update at once
+    fn update(&mut self, update: &TrayIconUpdate) -> Result<(), Error> {
+        if update.icon.is_some() {
+            self.pending_icon = None;
+        }
+        if update.tooltip.is_some() {
+            self.dynamic_tooltip_text = None;
+        }
+        if !self.notify_icon.update(
+            update.icon.as_ref().map(|icon| &icon.sys),
+            update.tooltip.as_deref(),
+            update.visible,
+        ) {
+            return Err(Error::OsError);
+        }
+        if update.icon.is_some() {
+            self.icon = update.icon.clone();
+        }
+        Ok(())
+    }
+
+    /// Show notification
+    fn show_notification(
+        &mut self,
+        title: &str,
+        message: &str,
+        options: &NotificationOptions,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "toast")]
+        if let Some(toasts) = self.toasts.as_mut() {
+            let actions = self
+                .notification_actions
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect::<Vec<_>>();
+            if toasts.show(self.hwnd, title, message, &actions, options.silent) {
+                return Ok(());
+            }
+        }
+        if !self.notify_icon.show_notification(title, message, options) {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+
+    /// Ignore or handle the clicks and menu commands
+    fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+        if !interactive {
+            // Drop the pending delayed click
+            unsafe { winuser::KillTimer(self.hwnd, CLICK_TIMER_ID) };
+            self.delayed_click = None;
+            self.skip_click = false;
+        }
+    }
+
+    /// Hide the notification
+    fn clear_notification(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "toast")]
+        if self.toasts.as_mut().is_some_and(|toasts| toasts.hide()) {
+            return Ok(());
+        }
+        if !self.notify_icon.hide_notification() {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+
+    /// Re-add the icon
+    fn request_show(&mut self) -> Result<(), Error> {
+        self.removed = false;
+        self.notify_icon.request_show()
+    }
+
+    /// Add the removed icon back with its current state
+    fn restore(&mut self) -> Result<(), Error> {
+        self.removed = false;
+        self.notify_icon.add(self.hwnd)
+    }
+
+    /// Quit the message loop when the window is destroyed
+    fn set_quit_on_close(&mut self, quit: bool) {
+        self.quit_on_close = quit;
+    }
+
+    /// Block the session end with the reason shown to the user, or allow it
+    fn block_shutdown(&mut self, reason: Option<&str>) -> Result<(), Error> {
+        let res = match reason {
+            Some(reason) => unsafe {
+                winuser::ShutdownBlockReasonCreate(self.hwnd, wchar(reason).as_ptr())
+            },
+            None => unsafe { winuser::ShutdownBlockReasonDestroy(self.hwnd) },
+        };
+        if res == 0 {
+            return Err(Error::OsError);
+        }
+        self.shutdown_blocked = reason.is_some();
+        Ok(())
+    }
+
+    /// Replace the sender, the following events go to the new sender
+    fn set_sender(&mut self, sender: TrayIconSender<T>) {
+        self.sender = Some(sender);
+    }
+
+    /// Remove the icon, it's not added back when the taskbar is recreated
+    fn remove(&mut self) -> Result<(), Error> {
+        self.removed = true;
+        if !self.notify_icon.remove() {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+
+    /// Show the menu next to the icon, or at the cursor
+    fn open_menu(&mut self) -> Result<Option<T>, Error> {
+        let pos = match self.notify_icon.get_rect() {
+            Some(rect) => menu_position(&rect),
+            None => unsafe {
+                let mut pos = POINT { x: 0, y: 0 };
+                winuser::GetCursorPos(&mut pos as _);
+                pos
+            },
+        };
+        self.show_menu_at(pos.x, pos.y)
+    }
+
+    /// Show the menu at screen coordinates
+    fn show_menu_at(&mut self, x: i32, y: i32) -> Result<Option<T>, Error> {
+        if !self.interactive {
+            return Ok(None);
+        }
+        self.track_menu(x, y)
+    }
+
+    /// Is the calling thread the thread of the window
+    fn is_on_message_thread(&self) -> bool {
+        unsafe {
+            winuser::GetWindowThreadProcessId(self.hwnd, std::ptr::null_mut())
+                == winapi::um::processthreadsapi::GetCurrentThreadId()
+        }
+    }
+
+    /// Handle of the hidden window as an integer
+    fn window_handle(&self) -> usize {
+        self.hwnd as usize
+    }
+
+    /// Handle of the hidden window
+    #[cfg(feature = "raw-window-handle")]
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        raw_window_handle::RawWindowHandle::Windows(raw_window_handle::windows::WindowsHandle {
+            hwnd: self.hwnd as _,
+            hinstance: module_handle() as _,
+            ..raw_window_handle::windows::WindowsHandle::empty()
+        })
+    }
+
+    /// Post the input to the window like the shell would
+    #[cfg(any(test, feature = "testing"))]
+    fn simulate(&self, input: &crate::SimulatedInput<T>) -> Result<(), Error> {
+        let callback_message = self.notify_icon.callback_message();
+        let (msg, wparam, lparam) = match input {
+            crate::SimulatedInput::Click => (callback_message, 0, winuser::WM_LBUTTONUP),
+            crate::SimulatedInput::DoubleClick => (callback_message, 0, winuser::WM_LBUTTONDBLCLK),
+            crate::SimulatedInput::RightClick => (callback_message, 0, winuser::WM_RBUTTONUP),
+            crate::SimulatedInput::MenuItem(event) => {
+                let identifier = self
+                    .menu
+                    .as_ref()
+                    .and_then(|menu| menu.ids.iter().find(|(_, e)| *e == event))
+                    .map(|(identifier, _)| *identifier)
+                    .ok_or(Error::MenuItemNotFound)?;
+                (winuser::WM_COMMAND, identifier, 0)
+            }
+            crate::SimulatedInput::TaskbarCreated => (self.wm_taskbarcreated, 0, 0),
+        };
+        if unsafe { winuser::PostMessageA(self.hwnd, msg, wparam, lparam as _) } == 0 {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+
+    /// Error code of the latest failed call of the notify icon
+    fn last_os_error(&self) -> Option<u32> {
+        self.notify_icon.last_os_error()
+    }
+
+    /// Is the icon visible in the notification area
+    fn is_visible(&self) -> bool {
+        match self.notify_icon.get_rect() {
+            Some(rect) => rect.right > rect.left && rect.bottom > rect.top,
+            None => false,
+        }
+    }
+
+    /// Modify the icon with fields set by the function
+    fn with_notify_data(&mut self, f: &mut dyn FnMut(*mut std::ffi::c_void)) -> Result<(), Error> {
+        if !self.notify_icon.modify_with(f) {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+
+    /// Location of the icon from the shell
+    fn icon_rect(&self) -> Option<Rect> {
+        self.notify_icon.get_rect().map(Rect::from)
+    }
+
+    /// Dispatch messages of this window until the queue is empty
+    fn pump_messages(&mut self) {
+        unsafe {
+            let mut msg = std::mem::zeroed();
+            while winuser::PeekMessageA(&mut msg, self.hwnd, 0, 0, winuser::PM_REMOVE) != 0 {
+                winuser::TranslateMessage(&msg);
+                winuser::DispatchMessageA(&msg);
+            }
+        }
+    }
+
     /// Set icon
     fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
+        self.pending_icon = None;
         if !self.notify_icon.set_icon(&icon.sys) {
-            return Err(Error::IconLoadingFailed);
+            return Err(Error::OsError);
         }
+        self.icon = Some(icon.clone());
+        Ok(())
+    }
+
+    /// Set icon at most once per timer interval
+    ///
+    /// First update is applied immediately, after that only the latest icon is
+    /// applied when the timer fires.
+    fn set_icon_coalesced(&mut self, icon: &Icon) -> Result<(), Error> {
+        if self.icon_timer {
+            self.pending_icon = Some(icon.clone());
+            return Ok(());
+        }
+        if !self.notify_icon.set_icon(&icon.sys) {
+            return Err(Error::OsError);
+        }
+        self.icon = Some(icon.clone());
+        if unsafe { winuser::SetTimer(self.hwnd, ICON_TIMER_ID, ICON_TIMER_INTERVAL, None) } == 0 {
+            return Err(Error::OsError);
+        }
+        self.icon_timer = true;
         Ok(())
     }
 
@@ -253,12 +1204,78 @@ where
     }
 }
 
+/// Prints the window handle and which options are set, the events themselves
+/// need not be `Debug`
+impl<T> Debug for TrayIconWindow<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let events = [
+            ("on_click", self.on_click.is_some()),
+            ("on_double_click", self.on_double_click.is_some()),
+            ("on_right_click", self.on_right_click.is_some()),
+            ("on_ready", self.on_ready.is_some()),
+            (
+                "on_high_contrast_changed",
+                self.on_high_contrast_changed.is_some(),
+            ),
+            ("on_session_end_query", self.on_session_end_query.is_some()),
+            ("on_session_end", self.on_session_end.is_some()),
+            ("on_taskbar_moved", self.on_taskbar_moved.is_some()),
+            (
+                "on_notification_click",
+                self.on_notification_click.is_some(),
+            ),
+            (
+                "on_notification_timeout",
+                self.on_notification_timeout.is_some(),
+            ),
+            (
+                "on_notification_hidden",
+                self.on_notification_hidden.is_some(),
+            ),
+            ("on_hover", self.on_hover.is_some()),
+            ("on_unknown_menu_id", self.on_unknown_menu_id.is_some()),
+            ("on_menu_cancelled", self.on_menu_cancelled.is_some()),
+            ("on_menu_closed", self.on_menu_closed.is_some()),
+            ("on_click_info", self.on_click_info.is_some()),
+            ("on_menu_init", self.on_menu_init.is_some()),
+            ("on_raw_message", self.on_raw_message.is_some()),
+        ];
+        let events: Vec<&str> = events
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect();
+        f.debug_struct("TrayIconWindow")
+            .field("hwnd", &format_args!("{:#x}", self.hwnd as usize))
+            .field("menu", &self.menu.is_some())
+            .field("dynamic_menu", &self.dynamic_menu.is_some())
+            .field("dynamic_tooltip", &self.dynamic_tooltip.is_some())
+            .field("sender", &self.sender.is_some())
+            .field("handler", &self.handler.is_some())
+            .field("events", &events)
+            .field("interactive", &self.interactive)
+            .field("removed", &self.removed)
+            .finish()
+    }
+}
+
 impl<T> Drop for TrayIconWindow<T>
 where
     T: PartialEq + Clone + 'static,
 {
     fn drop(&mut self) {
+        // Window is destroyed synchronously, the subclass is removed in
+        // WM_NCDESTROY before this memory is freed.
+        //
         // https://devblogs.microsoft.com/oldnewthing/20110926-00/?p=9553
         unsafe { winuser::SendMessageA(self.hwnd, winuser::WM_CLOSE, 0, 0) };
+
+        // Class is unregistered with the last window, so nothing refers to
+        // the code of an unloaded DLL. Fails while other windows remain.
+        let _class_lock = CLASS_LOCK.lock();
+        unsafe { winuser::UnregisterClassA(self.class_name.as_ptr() as _, module_handle()) };
     }
 }
@@ -0,0 +1,19 @@
+/// Input simulated with `TrayIcon::simulate`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulatedInput<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    Click,
+    DoubleClick,
+
+    /// Shows the menu like a real right click does, and blocks the message
+    /// loop until the menu is closed
+    RightClick,
+
+    /// Selects the menu item with the event
+    MenuItem(T),
+
+    /// Explorer was restarted, the icon is added again
+    TaskbarCreated,
+}
@@ -0,0 +1,163 @@
+use crate::MenuInit;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// Tray Icon event handler
+///
+/// Alternative to the events of the builder, the handler is called directly
+/// from the message loop of the tray icon and can own its state. All methods
+/// do nothing by default, implement only the ones you need.
+///
+/// Handler is called in addition to sending the builder events, so both can
+/// be used at the same time.
+pub trait TrayEventHandler<T> {
+    fn on_left_click(&mut self) {}
+    fn on_right_click(&mut self) {}
+    fn on_double_click(&mut self) {}
+
+    /// Menu item was selected, `event` is the event of the menu item
+    fn on_menu(&mut self, _event: &T) {}
+
+    /// Menu was closed, after `on_menu` if an item was selected
+    fn on_menu_closed(&mut self) {}
+
+    /// Balloon notification was clicked
+    fn on_balloon_click(&mut self) {}
+
+    /// Balloon notification timed out or was closed with the X button
+    fn on_balloon_timeout(&mut self) {}
+
+    /// Balloon notification was removed without timing out or a click
+    fn on_balloon_hidden(&mut self) {}
+}
+
+/// Shared handler, builder and the tray icon window have the same handler
+pub(crate) struct EventHandler<T>(Arc<Mutex<dyn TrayEventHandler<T> + Send>>);
+
+impl<T> EventHandler<T> {
+    pub fn new<H>(handler: H) -> EventHandler<T>
+    where
+        H: TrayEventHandler<T> + Send + 'static,
+    {
+        EventHandler(Arc::new(Mutex::new(handler)))
+    }
+
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn call<F>(&self, f: F)
+    where
+        F: FnOnce(&mut dyn TrayEventHandler<T>),
+    {
+        if let Ok(mut handler) = self.0.lock() {
+            f(&mut *handler);
+        }
+    }
+}
+
+impl<T> Clone for EventHandler<T> {
+    fn clone(&self) -> Self {
+        EventHandler(self.0.clone())
+    }
+}
+
+impl<T> Debug for EventHandler<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventHandler")
+    }
+}
+
+/// Raw window message hook, see `TrayIconBuilder::on_raw_message`
+#[derive(Clone)]
+pub(crate) struct RawMessageHook(
+    Arc<dyn Fn(usize, u32, usize, isize) -> Option<isize> + Send + Sync>,
+);
+
+impl RawMessageHook {
+    pub fn new<F>(f: F) -> RawMessageHook
+    where
+        F: Fn(usize, u32, usize, isize) -> Option<isize> + Send + Sync + 'static,
+    {
+        RawMessageHook(Arc::new(f))
+    }
+
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn call(&self, hwnd: usize, msg: u32, wparam: usize, lparam: isize) -> Option<isize> {
+        (self.0)(hwnd, msg, wparam, lparam)
+    }
+}
+
+impl Debug for RawMessageHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RawMessageHook")
+    }
+}
+
+type MenuInitFn<T> = dyn Fn(&mut MenuInit<T>) + Send + Sync;
+
+/// Menu init hook, see `TrayIconBuilder::on_menu_init`
+pub(crate) struct MenuInitHook<T>(Arc<MenuInitFn<T>>)
+where
+    T: PartialEq + Clone + 'static;
+
+impl<T> MenuInitHook<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    pub fn new<F>(f: F) -> MenuInitHook<T>
+    where
+        F: Fn(&mut MenuInit<T>) + Send + Sync + 'static,
+    {
+        MenuInitHook(Arc::new(f))
+    }
+
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn call(&self, menu: &mut MenuInit<T>) {
+        (self.0)(menu)
+    }
+}
+
+impl<T> Clone for MenuInitHook<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn clone(&self) -> Self {
+        MenuInitHook(self.0.clone())
+    }
+}
+
+impl<T> Debug for MenuInitHook<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MenuInitHook")
+    }
+}
+
+/// Closure producing a value on demand, e.g. a menu built when it's opened
+pub(crate) struct Callback<R>(Arc<dyn Fn() -> R + Send + Sync>);
+
+impl<R> Callback<R> {
+    pub fn new<F>(f: F) -> Callback<R>
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+    {
+        Callback(Arc::new(f))
+    }
+
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn call(&self) -> R {
+        (self.0)()
+    }
+}
+
+impl<R> Clone for Callback<R> {
+    fn clone(&self) -> Self {
+        Callback(self.0.clone())
+    }
+}
+
+impl<R> Debug for Callback<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Callback")
+    }
+}
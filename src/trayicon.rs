@@ -1,4 +1,8 @@
-use crate::{Error, Icon, MenuBuilder, TrayIconBase, TrayIconBuilder};
+use crate::{
+    trayiconsender::TrayIconSender, Error, Icon, MenuBuilder, NotificationOptions, Point, Rect,
+    StockIcon, TrayIconBase, TrayIconBuilder, TrayIconUpdate,
+};
+use std::sync::{Arc, Mutex};
 
 pub struct TrayIcon<T>
 where
@@ -6,6 +10,22 @@ where
 {
     sys: Box<crate::TrayIconSys<T>>,
     builder: TrayIconBuilder<T>,
+    icon_cache: Vec<Icon>,
+    notifications_enabled: bool,
+    interactive: bool,
+    shutdown_reason: Option<String>,
+    removed: bool,
+}
+
+/// Maximum number of icons in the icon cache
+const ICON_CACHE_SIZE: usize = 16;
+
+/// Icon loaded again for the current DPI, or the same icon
+fn reloaded(icon: &Icon) -> Icon {
+    match icon.reload() {
+        Some(Ok(icon)) => icon,
+        _ => icon.clone(),
+    }
 }
 
 impl<T> TrayIcon<T>
@@ -13,16 +33,175 @@ where
     T: PartialEq + Clone + 'static,
 {
     pub(crate) fn new(sys: Box<crate::TrayIconSys<T>>, builder: TrayIconBuilder<T>) -> TrayIcon<T> {
-        TrayIcon { builder, sys }
+        TrayIcon {
+            builder,
+            sys,
+            icon_cache: vec![],
+            notifications_enabled: true,
+            interactive: true,
+            shutdown_reason: None,
+            removed: false,
+        }
+    }
+
+    /// Rebuild the window and the icon with changed options
+    ///
+    /// The function gets a builder with the current icon, tooltip, menu,
+    /// events and sender, and changes the options fixed at creation, e.g.
+    /// `notify_version`, `promote`, `callback_message`, `window_class` or
+    /// `message_only`. The old icon is removed first so that the new one can
+    /// take its GUID, and if building fails the old icon is added back as it
+    /// was. The old window is destroyed without quitting the message loop,
+    /// even with `quit_on_close`.
+    ///
+    /// State set after creation carries over: `set_interactive`,
+    /// `block_shutdown` and `remove`, a removed icon stays removed.
+    pub fn recreate_with<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(TrayIconBuilder<T>) -> TrayIconBuilder<T>,
+    {
+        let mut builder = f(self.builder.clone());
+
+        // DPI may have changed since the icons were loaded
+        if let Ok(icon) = &builder.icon {
+            builder.icon = Ok(reloaded(icon));
+        }
+        builder.active_icon = builder.active_icon.as_ref().map(reloaded);
+        let _ = self.sys.remove();
+        match crate::build_trayicon(&builder) {
+            Ok(mut sys) => {
+                // Icon is added when the messages are processed, so removing
+                // it now keeps it from showing up at all
+                if self.removed {
+                    let _ = sys.remove();
+                }
+                sys.set_interactive(self.interactive);
+                let mut old = std::mem::replace(&mut self.sys, sys);
+                old.set_quit_on_close(false);
+                if let Some(reason) = &self.shutdown_reason {
+                    let _ = old.block_shutdown(None);
+                    let _ = self.sys.block_shutdown(Some(reason));
+                }
+                self.builder = builder;
+                Ok(())
+            }
+            Err(e) => {
+                if !self.removed {
+                    let _ = self.sys.restore();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Tag given with `TrayIconBuilder::tag`
+    pub fn tag(&self) -> Option<usize> {
+        self.builder.tag
     }
 
     /// Set the icon if changed
+    ///
+    /// Returns `Error::OsError` if the shell didn't accept the icon, e.g. while
+    /// Explorer is restarting, setting the same icon again retries. Before the
+    /// icon is added to the tray the change is stored and included when it's
+    /// added.
     pub fn set_icon(&mut self, icon: &Icon) -> Result<(), Error> {
         if self.builder.icon.as_ref() == Ok(icon) {
             return Ok(());
         }
+        self.sys.set_icon(icon)?;
         self.builder.icon = Ok(icon.clone());
-        self.sys.set_icon(icon)
+        Ok(())
+    }
+
+    /// Set the icon if changed, coalescing rapid updates
+    ///
+    /// Meant for animations and other high frequency updates. The icon is
+    /// updated at most once per 100ms, if several icons are set in between
+    /// only the latest one is shown. Use `set_icon` for one-off changes, it's
+    /// applied immediately and cancels a pending coalesced icon.
+    pub fn set_icon_coalesced(&mut self, icon: &Icon) -> Result<(), Error> {
+        if self.builder.icon.as_ref() == Ok(icon) {
+            return Ok(());
+        }
+        self.sys.set_icon_coalesced(icon)?;
+        self.builder.icon = Ok(icon.clone());
+        Ok(())
+    }
+
+    /// Set a standard system icon if changed
+    pub fn set_icon_from_stock(&mut self, stock: StockIcon) -> Result<(), Error> {
+        let icon = match self.take_cached_icon(|icon| icon.is_from_stock(stock)) {
+            Some(icon) => icon,
+            None => Icon::from_stock(stock)?,
+        };
+        self.cache_icon(&icon);
+        self.set_icon(&icon)
+    }
+
+    /// Set the icon from a buffer if changed
+    ///
+    /// Accepts the same buffers as `Icon::from_buffer`. Icons set this way are
+    /// cached, so cycling through a fixed set of icons decodes each of them
+    /// only once.
+    ///
+    /// Width or height of `None` is the recommended small icon size of the
+    /// system (`SM_CXSMICON` and `SM_CYSMICON`) at the DPI of the taskbar, so
+    /// it's usually best to leave both out.
+    pub fn set_icon_from_buffer<B>(
+        &mut self,
+        buffer: B,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<(), Error>
+    where
+        B: AsRef<[u8]>,
+    {
+        let buffer = buffer.as_ref();
+        let icon = match self.take_cached_icon(|icon| icon.is_from_buffer(buffer, width, height)) {
+            Some(icon) => icon,
+            None => Icon::from_buffer(buffer, width, height)?,
+        };
+        self.cache_icon(&icon);
+        self.set_icon(&icon)
+    }
+
+    /// Set the icon from an owned buffer if changed
+    ///
+    /// Like `set_icon_from_buffer`, but the buffer is kept without copying it.
+    pub fn set_icon_from_owned(
+        &mut self,
+        buffer: Vec<u8>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<(), Error> {
+        let icon = match self.take_cached_icon(|icon| icon.is_from_buffer(&buffer, width, height)) {
+            Some(icon) => icon,
+            None => Icon::from_owned(buffer, width, height)?,
+        };
+        self.cache_icon(&icon);
+        self.set_icon(&icon)
+    }
+
+    /// Remove a matching icon from the cache
+    fn take_cached_icon<F>(&mut self, matches: F) -> Option<Icon>
+    where
+        F: Fn(&Icon) -> bool,
+    {
+        let i = self.icon_cache.iter().position(matches)?;
+        Some(self.icon_cache.remove(i))
+    }
+
+    /// Add the icon as the most recently used one
+    ///
+    /// Cache is bounded, the least recently used icon is evicted first. Icon
+    /// handles are freed when they are evicted or when the tray icon is
+    /// dropped.
+    fn cache_icon(&mut self, icon: &Icon) {
+        if self.icon_cache.len() >= ICON_CACHE_SIZE {
+            self.icon_cache.remove(0);
+        }
+        self.icon_cache.push(icon.clone());
     }
 
     /// Set the menu if changed
@@ -35,17 +214,293 @@ where
         if self.builder.menu.as_ref() == Some(menu) {
             return Ok(());
         }
+        self.sys.set_menu(menu)?;
         self.builder.menu = Some(menu.clone());
-        self.sys.set_menu(menu)
+        Ok(())
     }
 
     /// Set the tooltip if changed
+    ///
+    /// Windows limits the tooltip to 127 UTF-16 code units, longer tooltips
+    /// are truncated with an ellipsis.
+    ///
+    /// Tooltip is also the accessible name of the icon read by screen readers,
+    /// so an empty tooltip shows the name of the executable instead.
     pub fn set_tooltip(&mut self, tooltip: &str) -> Result<(), Error> {
         if self.builder.tooltip.as_deref() == Some(tooltip) {
             return Ok(());
         }
+        self.sys.set_tooltip(tooltip)?;
         self.builder.tooltip = Some(tooltip.to_string());
-        self.sys.set_tooltip(tooltip)
+        Ok(())
+    }
+
+    /// Change several properties at once
+    ///
+    /// Changes are applied with a single call to the shell, e.g. the icon and
+    /// the tooltip never appear out of sync. Unchanged icon and tooltip are
+    /// skipped like with `set_icon` and `set_tooltip`.
+    pub fn update<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut TrayIconUpdate),
+    {
+        let mut update = TrayIconUpdate::default();
+        f(&mut update);
+        if update.icon.is_some() && self.builder.icon.as_ref().ok() == update.icon.as_ref() {
+            update.icon = None;
+        }
+        if update.tooltip.is_some() && self.builder.tooltip == update.tooltip {
+            update.tooltip = None;
+        }
+        if update.is_empty() {
+            return Ok(());
+        }
+        self.sys.update(&update)?;
+        if let Some(icon) = &update.icon {
+            self.builder.icon = Ok(icon.clone());
+        }
+        if let Some(tooltip) = &update.tooltip {
+            self.builder.tooltip = Some(tooltip.clone());
+        }
+        Ok(())
+    }
+
+    /// Show a balloon notification
+    ///
+    /// Title and message may contain any Unicode text, too long strings are
+    /// truncated to fit the Windows limits (63 and 255 UTF-16 code units).
+    pub fn show_notification(&mut self, title: &str, message: &str) -> Result<(), Error> {
+        self.show_notification_with(title, message, &NotificationOptions::default())
+    }
+
+    /// Show a balloon notification with options, e.g. realtime
+    pub fn show_notification_with(
+        &mut self,
+        title: &str,
+        message: &str,
+        options: &NotificationOptions,
+    ) -> Result<(), Error> {
+        if !self.notifications_enabled {
+            return Ok(());
+        }
+        self.sys.show_notification(title, message, options)
+    }
+
+    /// Enable or disable the notifications, enabled by default
+    ///
+    /// While disabled showing a notification does nothing and returns `Ok`,
+    /// e.g. for a do not disturb mode of the application. Notifications aren't
+    /// queued, nothing is shown when they are enabled again.
+    pub fn set_notifications_enabled(&mut self, enabled: bool) {
+        self.notifications_enabled = enabled;
+    }
+
+    /// Hide the balloon notification, if it's still showing
+    ///
+    /// E.g. when the condition the notification was about has resolved.
+    pub fn clear_notification(&mut self) -> Result<(), Error> {
+        self.sys.clear_notification()
+    }
+
+    /// Show the menu next to the icon
+    ///
+    /// Menu opens away from the taskbar, if the shell doesn't know the location
+    /// of the icon the menu is shown at the cursor. Useful e.g. for a hotkey
+    /// that opens the tray menu.
+    ///
+    /// Blocks until the menu closes, and returns the event of the selected
+    /// item or `None` if the menu was cancelled. The event is also sent as
+    /// usual.
+    pub fn open_menu(&mut self) -> Result<Option<T>, Error> {
+        self.sys.open_menu()
+    }
+
+    /// Show the menu at screen coordinates
+    ///
+    /// Useful for showing the menu from a keyboard shortcut or other flows
+    /// that don't involve clicking the icon. Returns the selected event like
+    /// `open_menu`.
+    pub fn show_menu_at(&mut self, position: Point) -> Result<Option<T>, Error> {
+        self.sys.show_menu_at(position.x, position.y)
+    }
+
+    /// Is the calling thread the one that created the tray icon
+    ///
+    /// The thread that creates the tray icon must run a message loop, e.g. the
+    /// event loop of a GUI library or `pump_messages`, otherwise the icon isn't
+    /// even added to the tray and no events are sent. Messages are processed
+    /// only by that thread, so e.g. a tray icon created on a worker thread that
+    /// only waits on a channel does nothing. Useful for asserting that the
+    /// icon is used where the loop runs, or use `build_detached` to get a
+    /// thread with its own loop.
+    pub fn is_on_message_thread(&self) -> bool {
+        self.sys.is_on_message_thread()
+    }
+
+    /// Handle of the hidden window of the tray icon
+    ///
+    /// The handle is a plain integer so that the public API doesn't depend on
+    /// a version of `winapi`, cast it to the `HWND` of the bindings in use,
+    /// e.g. `handle as winapi::shared::windef::HWND` or `HWND(handle as _)` of
+    /// the `windows` crate. With the `raw-window-handle` feature `TrayIcon`
+    /// implements `HasRawWindowHandle` too.
+    pub fn window_handle(&self) -> usize {
+        self.sys.window_handle()
+    }
+
+    /// Change uncommon fields of the notify icon data
+    ///
+    /// The function gets a pointer to the `NOTIFYICONDATAW` of the icon with
+    /// `uFlags` cleared, it sets the fields and the flags of the fields to
+    /// change, and then the icon is modified with `NIM_MODIFY`. Changed fields
+    /// and their flags are kept and sent also when the icon is added again,
+    /// e.g. after Explorer restarts. Balloon fields of `NIF_INFO` aren't, the
+    /// balloon is shown only once.
+    ///
+    /// This is an escape hatch, changing the window, id, callback message or
+    /// size of the data breaks the tray icon.
+    pub fn with_notify_data<F>(&mut self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(*mut std::ffi::c_void),
+    {
+        self.sys.with_notify_data(&mut f)
+    }
+
+    /// Location of the icon on the screen
+    ///
+    /// E.g. for placing a custom popup next to the icon. `None` if the shell
+    /// doesn't know the location, see `is_visible`.
+    pub fn icon_rect(&self) -> Option<Rect> {
+        self.sys.icon_rect()
+    }
+
+    /// Is the icon visible, best effort
+    ///
+    /// Asks the location of the icon from the shell, if it doesn't have one the
+    /// icon is considered hidden. Windows doesn't notify when the user moves
+    /// the icon to the overflow area or turns it off in the settings, so this
+    /// must be polled. Depending on the Windows version an icon in the
+    /// overflow area may still report a location, and thus be visible.
+    pub fn is_visible(&self) -> bool {
+        self.sys.is_visible()
+    }
+
+    /// Error code of the latest failed call to the shell, e.g. `GetLastError`
+    ///
+    /// Covers the failures that can't be returned, like adding the icon when
+    /// the window is created or when the taskbar is recreated. Useful for
+    /// diagnosing an icon that never appears.
+    pub fn last_os_error(&self) -> Option<u32> {
+        self.sys.last_os_error()
+    }
+
+    /// Ask the shell to show the icon, best effort
+    ///
+    /// Removes and adds the icon again without hidden or shared state, which
+    /// may nudge the shell to surface it. Windows decides where the icon is
+    /// shown and is free to ignore this, the user settings always win.
+    pub fn request_show(&mut self) -> Result<(), Error> {
+        self.removed = false;
+        self.sys.request_show()
+    }
+
+    /// Block logging off and shutting down, e.g. while saving
+    ///
+    /// While blocked, the session end is refused and Windows shows the reason
+    /// with an option to end the session anyway. Block only briefly, and
+    /// allow the session end again with `None` as soon as possible. The
+    /// `on_session_end_query` event is sent either way.
+    pub fn block_shutdown(&mut self, reason: Option<&str>) -> Result<(), Error> {
+        self.sys.block_shutdown(reason)?;
+        self.shutdown_reason = reason.map(|v| v.to_string());
+        Ok(())
+    }
+
+    /// Ignore clicks and menu commands while not interactive
+    ///
+    /// Icon stays visible, but no click or menu events are sent and the menu
+    /// isn't shown, `open_menu` and `show_menu_at` return `Ok(None)`.
+    /// Notification, hover and other events are sent as usual. Useful e.g.
+    /// during a busy state to avoid triggering actions mid-way.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+        self.sys.set_interactive(interactive)
+    }
+
+    /// Replace the event sender
+    ///
+    /// The following events, including the menu events, are sent to the new
+    /// sender instead of the one given to the builder, e.g. when the
+    /// application moves its event handling to another channel. The icon is
+    /// kept as it is, and `recreate_with` keeps the new sender.
+    pub fn set_sender(&mut self, sender: std::sync::mpsc::Sender<T>) {
+        self.replace_sender(TrayIconSender::Std(sender));
+    }
+
+    /// Replace the event sender with a queue, see `TrayIconBuilder::sender_poll`
+    ///
+    /// Events sent before this are not in the queue.
+    pub fn set_sender_poll(&mut self) {
+        self.replace_sender(TrayIconSender::Poll(Arc::new(Mutex::new(vec![]))));
+    }
+
+    /// Replace the event sender, requires `winit` feature
+    #[cfg(feature = "winit")]
+    pub fn set_sender_winit(&mut self, sender: winit::event_loop::EventLoopProxy<T>) {
+        self.replace_sender(TrayIconSender::Winit(sender));
+    }
+
+    /// Replace the event sender, requires `crossbeam-channel` feature
+    #[cfg(feature = "crossbeam-channel")]
+    pub fn set_sender_crossbeam(&mut self, sender: crossbeam_channel::Sender<T>) {
+        self.replace_sender(TrayIconSender::Crossbeam(sender));
+    }
+
+    fn replace_sender(&mut self, sender: TrayIconSender<T>) {
+        self.sys.set_sender(sender.clone());
+        self.builder.sender = Some(sender);
+    }
+
+    /// Remove the icon from the notification area immediately
+    ///
+    /// E.g. before showing a modal dialog. Dropping the tray icon removes it
+    /// too, removing it first is fine. The icon stays removed until
+    /// `request_show` adds it back.
+    pub fn remove(&mut self) -> Result<(), Error> {
+        self.removed = true;
+        self.sys.remove()
+    }
+
+    /// Simulate user input, requires `testing` feature
+    ///
+    /// Input is posted to the tray icon window, the events arrive after the
+    /// message loop has processed it. Meant for testing the event handling of
+    /// the application without a human.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn simulate(&self, input: &crate::SimulatedInput<T>) -> Result<(), Error> {
+        self.sys.simulate(input)
+    }
+
+    /// Process pending messages of the tray icon without blocking
+    ///
+    /// Returns the events queued since the previous call, if the builder was
+    /// given `sender_poll`, with other senders the events are sent to the
+    /// channel as usual and the returned vector is empty.
+    ///
+    /// This must be called on the thread that created the tray icon.
+    pub fn pump_messages(&mut self) -> Vec<T> {
+        debug_assert!(
+            self.sys.is_on_message_thread(),
+            "pump_messages called on another thread than the one that created the tray icon"
+        );
+        self.sys.pump_messages();
+        match &self.builder.sender {
+            Some(TrayIconSender::Poll(q)) => q
+                .lock()
+                .map(|mut q| q.drain(..).collect())
+                .unwrap_or_default(),
+            _ => vec![],
+        }
     }
 
     /// Set disabled
@@ -91,3 +546,17 @@ where
 unsafe impl<T> Sync for TrayIcon<T> where T: PartialEq + Clone + 'static {}
 
 unsafe impl<T> Send for TrayIcon<T> where T: PartialEq + Clone + 'static {}
+
+/// Handle of the hidden window of the tray icon, requires `raw-window-handle`
+/// feature
+///
+/// E.g. for anchoring popups of GUI libraries. The window is never shown.
+#[cfg(feature = "raw-window-handle")]
+unsafe impl<T> raw_window_handle::HasRawWindowHandle for TrayIcon<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    fn raw_window_handle(&self) -> raw_window_handle::RawWindowHandle {
+        self.sys.raw_window_handle()
+    }
+}
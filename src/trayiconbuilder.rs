@@ -1,12 +1,52 @@
-use crate::{trayiconsender::TrayIconSender, Icon, MenuBuilder, TrayIcon};
+use crate::{
+    trayeventhandler::{Callback, EventHandler, MenuInitHook, RawMessageHook},
+    trayiconsender::TrayIconSender,
+    ClickInfo, ClickKind, DetachedTrayIcon, Icon, MenuBuilder, MenuInit, TrayEventHandler,
+    TrayIcon,
+};
+use std::sync::{mpsc::Receiver, Arc, Mutex};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Error {
     MenuItemNotFound,
     IconLoadingFailed,
+
+    /// Icon loading failed with the OS error code, e.g. `GetLastError` value
+    IconLoadingOsError(u32),
     SenderMissing,
     IconMissing,
+    MenuMissing,
     OsError,
+
+    /// Notification area is not available, e.g. in a service running in
+    /// session 0, tray icons need an interactive user session
+    ShellUnavailable,
+
+    /// Callback message is outside of the `WM_APP` range
+    InvalidCallbackMessage,
+
+    /// Stable menu ids aren't unique or an id is zero, see
+    /// `MenuBuilder::stable_ids`
+    InvalidMenuId,
+
+    /// Left click can't both open the menu and send the click event, see
+    /// `TrayIconBuilder::menu_on_left_click`
+    ConflictingClickEvents,
+
+    /// Tray icons aren't implemented on this platform, only the menu model
+    /// can be used
+    Unsupported,
+}
+
+/// Notify icon protocol version
+///
+/// Version 4 is available since Windows Vista, if it's not supported the icon
+/// falls back to version 3 and then to the legacy behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyVersion {
+    V3,
+    V4,
 }
 
 // Why do I need to do this, can't Rust do this automatically?
@@ -23,6 +63,22 @@ impl From<&Error> for Error {
 /// uses `std::sync::mpsc::Sender<T>`, additionally if `winit` feature is
 /// enabled you can choose to use `winit::event_loop::EventLoopProxy<T>` or with
 /// `crossbeam-channel` feature the `crossbeam_channel::Sender<T>` is available.
+/// For loops that can't block there is also `sender_poll`, where events are
+/// returned from `TrayIcon::pump_messages`. Display only icons with just the
+/// icon and tooltip, without events or a menu, don't need a sender.
+///
+/// All events, including the menu events, go through the same sender. They
+/// are sent on the thread running the message loop of the tray icon while the
+/// message is handled, e.g. a menu event is sent when the menu closes. The
+/// receiver doesn't need to be waiting, events are queued until it reads them.
+/// Only a full bounded crossbeam channel drops events, since blocking the
+/// message loop would freeze the tray icon.
+///
+/// Events must be `Clone`, since the same configured event is sent each time
+/// the icon is clicked or the menu item is selected, and `PartialEq`, since
+/// menu items are found by their event. Events carrying data that can't be
+/// cloned can be wrapped in an `Arc`, or produced per occurrence with the
+/// function based events like `on_click_info` and `on_unknown_menu_id`.
 ///
 /// This is defined as consuming builder, this includes conditional helper
 /// `when` for composing conditionally some settings.
@@ -34,12 +90,47 @@ where
     T: PartialEq + Clone + 'static,
 {
     pub(crate) icon: Result<Icon, Error>,
+    pub(crate) active_icon: Option<Icon>,
     pub(crate) menu: Option<MenuBuilder<T>>,
+    pub(crate) dynamic_menu: Option<Callback<MenuBuilder<T>>>,
     pub(crate) tooltip: Option<String>,
+    pub(crate) dynamic_tooltip: Option<Callback<String>>,
     pub(crate) on_click: Option<T>,
     pub(crate) on_double_click: Option<T>,
     pub(crate) on_right_click: Option<T>,
+    pub(crate) on_ready: Option<T>,
+    pub(crate) on_high_contrast_changed: Option<T>,
+    pub(crate) on_session_end_query: Option<T>,
+    pub(crate) on_session_end: Option<T>,
+    pub(crate) on_taskbar_moved: Option<T>,
+    pub(crate) on_notification_click: Option<T>,
+    pub(crate) on_notification_timeout: Option<T>,
+    pub(crate) on_notification_hidden: Option<T>,
+    #[cfg(feature = "toast")]
+    pub(crate) toast_app_id: Option<String>,
+    #[cfg(feature = "toast")]
+    pub(crate) notification_actions: Vec<(String, T)>,
+    pub(crate) on_hover: Option<T>,
+    pub(crate) delay_click: bool,
+    pub(crate) double_click_default_item: bool,
+    pub(crate) auto_show_menu: bool,
+    pub(crate) menu_on_left_click: bool,
+    pub(crate) quit_on_close: bool,
+    pub(crate) tag: Option<usize>,
+    pub(crate) callback_message: Option<u32>,
+    pub(crate) window_class: Option<String>,
+    pub(crate) message_only: bool,
+    pub(crate) on_unknown_menu_id: Option<fn(usize) -> T>,
+    pub(crate) on_menu_cancelled: Option<T>,
+    pub(crate) on_menu_closed: Option<T>,
+    pub(crate) on_click_info: Option<fn(ClickInfo) -> T>,
+    pub(crate) notify_version: NotifyVersion,
+    pub(crate) guid: Option<u128>,
+    pub(crate) notification_timeout: Option<Duration>,
     pub(crate) sender: Option<TrayIconSender<T>>,
+    pub(crate) handler: Option<EventHandler<T>>,
+    pub(crate) on_raw_message: Option<RawMessageHook>,
+    pub(crate) on_menu_init: Option<MenuInitHook<T>>,
 }
 
 impl<T> TrayIconBuilder<T>
@@ -50,12 +141,47 @@ where
     pub fn new() -> TrayIconBuilder<T> {
         TrayIconBuilder {
             icon: Err(Error::IconMissing),
+            active_icon: None,
             menu: None,
+            dynamic_menu: None,
             tooltip: None,
+            dynamic_tooltip: None,
             on_click: None,
             on_double_click: None,
             on_right_click: None,
+            on_ready: None,
+            on_high_contrast_changed: None,
+            on_session_end_query: None,
+            on_session_end: None,
+            on_taskbar_moved: None,
+            on_notification_click: None,
+            on_notification_timeout: None,
+            on_notification_hidden: None,
+            #[cfg(feature = "toast")]
+            toast_app_id: None,
+            #[cfg(feature = "toast")]
+            notification_actions: vec![],
+            on_hover: None,
+            delay_click: false,
+            double_click_default_item: false,
+            auto_show_menu: true,
+            menu_on_left_click: false,
+            quit_on_close: false,
+            tag: None,
+            callback_message: None,
+            window_class: None,
+            message_only: false,
+            on_unknown_menu_id: None,
+            on_menu_cancelled: None,
+            on_menu_closed: None,
+            on_click_info: None,
+            notify_version: NotifyVersion::V4,
+            guid: None,
+            notification_timeout: None,
             sender: None,
+            handler: None,
+            on_raw_message: None,
+            on_menu_init: None,
         }
     }
 
@@ -72,6 +198,15 @@ where
         self
     }
 
+    /// Queue the events instead of sending them to a channel
+    ///
+    /// Events are returned from `TrayIcon::pump_messages`, call it e.g. once
+    /// per frame.
+    pub fn sender_poll(mut self) -> Self {
+        self.sender = Some(TrayIconSender::Poll(Arc::new(Mutex::new(vec![]))));
+        self
+    }
+
     /// Optional feature, requires `winit` feature
     #[cfg(feature = "winit")]
     pub fn sender_winit(mut self, s: winit::event_loop::EventLoopProxy<T>) -> Self {
@@ -80,12 +215,99 @@ where
     }
 
     /// Optional feature, requires `crossbeam-channel` feature
+    ///
+    /// Use an unbounded channel, events are dropped if a bounded one is full.
     #[cfg(feature = "crossbeam-channel")]
     pub fn sender_crossbeam(mut self, s: crossbeam_channel::Sender<T>) -> Self {
         self.sender = Some(TrayIconSender::Crossbeam(s));
         self
     }
 
+    /// Call the handler on clicks and menu selections
+    ///
+    /// Sender is optional with a handler, the builder events are sent only if
+    /// there is a sender.
+    pub fn handler<H>(mut self, handler: H) -> Self
+    where
+        H: TrayEventHandler<T> + Send + 'static,
+    {
+        self.handler = Some(EventHandler::new(handler));
+        self
+    }
+
+    /// Identifier of the tray icon, e.g. a key in a registry of icons
+    ///
+    /// Get it back with `TrayIcon::tag`. Several tray icons can share the
+    /// same sender, their events are told apart only by the event values, so
+    /// give each icon its own events, e.g. a variant carrying the tag.
+    pub fn tag(mut self, tag: usize) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Message the shell sends to the window on mouse events of the icon
+    ///
+    /// Must be in the `WM_APP` range from `0x8000` to `0xBFFF`, by default a
+    /// private `WM_USER` message is used. Each tray icon has its own window,
+    /// this is needed only when other code hooks the same window and relies on
+    /// the default message, e.g. with `on_raw_message`.
+    pub fn callback_message(mut self, message: u32) -> Self {
+        self.callback_message = Some(message);
+        self
+    }
+
+    /// Class name of the hidden window, defaults to `TrayIconCls`
+    ///
+    /// Useful e.g. for finding the window with `FindWindow` from another
+    /// process. Class is registered with the first window using it and
+    /// unregistered with the last one.
+    pub fn window_class(mut self, name: &str) -> Self {
+        self.window_class = Some(name.to_string());
+        self
+    }
+
+    /// Create a message-only window instead of a hidden top-level window
+    ///
+    /// Message-only windows don't get broadcast messages, so the icon isn't
+    /// added back when Explorer restarts, and the session end, high contrast
+    /// and DPI changes aren't noticed.
+    pub fn message_only(mut self, message_only: bool) -> Self {
+        self.message_only = message_only;
+        self
+    }
+
+    /// Notify icon protocol version, defaults to version 4
+    pub fn notify_version(mut self, version: NotifyVersion) -> Self {
+        self.notify_version = version;
+        self
+    }
+
+    /// Configure the icon for the best chance of staying visible, best effort
+    ///
+    /// Identifies the icon with the GUID and uses the version 4 protocol, so
+    /// Windows can remember the user's "always show" choice across restarts
+    /// and updates. Use a fixed GUID generated once for the application.
+    /// Windows decides where new icons are shown and is free to hide them.
+    ///
+    /// Windows ties the GUID to the path of the executable, if it doesn't
+    /// match the icon is added without the GUID.
+    pub fn promote(mut self, guid: u128) -> Self {
+        self.guid = Some(guid);
+        self.notify_version = NotifyVersion::V4;
+        self
+    }
+
+    /// Timeout of the balloon notifications
+    ///
+    /// Only older Windows versions use this, and clamp it between 10 and 30
+    /// seconds. Since Windows Vista the accessibility settings decide how long
+    /// notifications are shown.
+    pub fn notification_timeout(mut self, timeout: Duration) -> Self {
+        self.notification_timeout = Some(timeout);
+        self
+    }
+
+    /// Tooltip of the icon, truncated like in `TrayIcon::set_tooltip`
     pub fn tooltip(mut self, tooltip: &str) -> Self {
         self.tooltip = Some(tooltip.to_string());
         self
@@ -106,16 +328,300 @@ where
         self
     }
 
+    /// Set the click events from one function
+    ///
+    /// Function is called once for each kind of click, `None` means no event.
+    /// Replaces the click events set before, the individual setters can be
+    /// used afterwards to override some of them.
+    pub fn events<F>(mut self, mut f: F) -> Self
+    where
+        F: FnMut(ClickKind) -> Option<T>,
+    {
+        self.on_click = f(ClickKind::Left);
+        self.on_right_click = f(ClickKind::Right);
+        self.on_double_click = f(ClickKind::Double);
+        self
+    }
+
+    /// Event sent when high contrast theme is turned on or off
+    ///
+    /// Check the current state with `trayicon::is_high_contrast`.
+    pub fn on_high_contrast_changed(mut self, id: T) -> Self {
+        self.on_high_contrast_changed = Some(id);
+        self
+    }
+
+    /// Event sent when Windows asks whether it can log off or shut down
+    ///
+    /// This is the earliest moment, but the session end may still be
+    /// cancelled, e.g. by another application or by `TrayIcon::block_shutdown`.
+    /// Use `on_session_end` for the session really ending.
+    pub fn on_session_end_query(mut self, id: T) -> Self {
+        self.on_session_end_query = Some(id);
+        self
+    }
+
+    /// Event sent when Windows logs off or shuts down
+    ///
+    /// Sent once the session end can't be cancelled anymore, the process may
+    /// be terminated soon after, so save only what's quick. The icon is
+    /// removed right after the event.
+    pub fn on_session_end(mut self, id: T) -> Self {
+        self.on_session_end = Some(id);
+        self
+    }
+
+    /// Event sent when the taskbar position changes
+    ///
+    /// E.g. for repositioning custom popups. The window is registered for the
+    /// appbar notifications only if this is set.
+    pub fn on_taskbar_moved(mut self, id: T) -> Self {
+        self.on_taskbar_moved = Some(id);
+        self
+    }
+
+    /// Event for the mouse moving over the icon
+    ///
+    /// Sent on the first move, and again only after the mouse hasn't moved
+    /// over the icon for a second. E.g. for preloading data before a click.
+    pub fn on_hover(mut self, id: T) -> Self {
+        self.on_hover = Some(id);
+        self
+    }
+
+    /// Event for a click on the balloon notification
+    ///
+    /// Classic balloons can't have buttons, the whole balloon is clickable.
+    /// Toasts of the `toast` feature have buttons, see `notification_action`.
+    pub fn on_notification_click(mut self, id: T) -> Self {
+        self.on_notification_click = Some(id);
+        self
+    }
+
+    /// Event for the balloon notification closing without a click
+    ///
+    /// Sent when the balloon times out and also when the user closes it with
+    /// the X button, classic balloons don't tell these apart.
+    pub fn on_notification_timeout(mut self, id: T) -> Self {
+        self.on_notification_timeout = Some(id);
+        self
+    }
+
+    /// Event for the balloon notification disappearing otherwise
+    ///
+    /// Sent when the balloon is removed because the icon was removed or hidden,
+    /// or the notification was cleared.
+    pub fn on_notification_hidden(mut self, id: T) -> Self {
+        self.on_notification_hidden = Some(id);
+        self
+    }
+
+    /// Show the notifications as toasts of the application, `toast` feature
+    ///
+    /// Toasts can have buttons, see `notification_action`. The application
+    /// user model id must be registered, for unpackaged applications with a
+    /// Start menu shortcut having the id. If toasts aren't available the
+    /// classic balloons are shown instead.
+    ///
+    /// A click on the toast itself sends the `on_notification_click` event.
+    #[cfg(feature = "toast")]
+    pub fn toast_app_id(mut self, app_id: &str) -> Self {
+        self.toast_app_id = Some(app_id.to_string());
+        self
+    }
+
+    /// Add a button to the toast notifications, `toast` feature
+    ///
+    /// Clicking the button sends the event. Buttons are shown in the order of
+    /// adding, Windows shows at most five. Balloons have no buttons, so the
+    /// events are sent only for toasts, see `toast_app_id`.
+    #[cfg(feature = "toast")]
+    pub fn notification_action(mut self, label: &str, id: T) -> Self {
+        self.notification_actions.push((label.to_string(), id));
+        self
+    }
+
+    /// Don't send the click event when the icon is double clicked
+    ///
+    /// By default a double click sends also the click event. With this the
+    /// click event is delayed by the system double click time, and cancelled
+    /// if the double click happens. This adds latency to single clicks.
+    pub fn delay_click(mut self, delay: bool) -> Self {
+        self.delay_click = delay;
+        self
+    }
+
+    /// Double click selects the default item of the menu
+    ///
+    /// Off by default, the double click event is sent either way. See
+    /// `MenuBuilder::default_item`.
+    pub fn double_click_default_item(mut self, enabled: bool) -> Self {
+        self.double_click_default_item = enabled;
+        self
+    }
+
+    /// Show the menu on right click, enabled by default
+    ///
+    /// When disabled the right click only sends the right click event, and the
+    /// menu can be shown with `TrayIcon::open_menu` when the application wants.
+    pub fn auto_show_menu(mut self, auto_show: bool) -> Self {
+        self.auto_show_menu = auto_show;
+        self
+    }
+
+    /// Show the menu also on left click, disabled by default
+    ///
+    /// Menu takes the left click, so the click events aren't sent, and setting
+    /// `on_click` too is an error when building. Right click sends the right
+    /// click event and then shows the menu, as usual.
+    pub fn menu_on_left_click(mut self, enabled: bool) -> Self {
+        self.menu_on_left_click = enabled;
+        self
+    }
+
+    /// Quit the message loop of the thread when the tray icon is destroyed
+    ///
+    /// Disabled by default, dropping the tray icon only destroys its window so
+    /// it can be rebuilt while the application keeps running. Enable it e.g.
+    /// for a thread that exists only for the tray icon.
+    pub fn quit_on_close(mut self, quit: bool) -> Self {
+        self.quit_on_close = quit;
+        self
+    }
+
+    /// Event sent when the icon has been added to the notification area
+    ///
+    /// Sent also when the icon is added again after Explorer restarts.
+    pub fn on_ready(mut self, id: T) -> Self {
+        self.on_ready = Some(id);
+        self
+    }
+
+    /// Produce the tooltip when the mouse is over the icon
+    ///
+    /// For live tooltips, e.g. progress, the function is called when the mouse
+    /// moves over the icon and the tooltip is updated if it has changed. Takes
+    /// precedence over `tooltip` and `TrayIcon::set_tooltip` while hovering.
+    pub fn with_dynamic_tooltip<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.dynamic_tooltip = Some(Callback::new(f));
+        self
+    }
+
+    /// Event for a selected menu command id that has no event
+    ///
+    /// Items of `MenuBuilder` always have an event, this is a catch-all for
+    /// menu items added by other means. Function gets the raw command id.
+    pub fn on_unknown_menu_id(mut self, f: fn(usize) -> T) -> Self {
+        self.on_unknown_menu_id = Some(f);
+        self
+    }
+
+    /// Event for all clicks with the details of the click
+    ///
+    /// Alternative to the separate click, double click and right click events,
+    /// function gets the button, modifier keys and the position of the click.
+    /// Separate events are still sent if they are set too.
+    pub fn on_click_info(mut self, f: fn(ClickInfo) -> T) -> Self {
+        self.on_click_info = Some(f);
+        self
+    }
+
+    /// Event for a menu closed without selecting an item
+    ///
+    /// E.g. with escape or by clicking outside of the menu.
+    pub fn on_menu_cancelled(mut self, id: T) -> Self {
+        self.on_menu_cancelled = Some(id);
+        self
+    }
+
+    /// Event for a closed menu, whether an item was selected or not
+    ///
+    /// Sent after the event of the selected item or the cancel event, e.g. for
+    /// releasing resources tied to the open menu.
+    pub fn on_menu_closed(mut self, id: T) -> Self {
+        self.on_menu_closed = Some(id);
+        self
+    }
+
+    /// Update the states of the menu items right before the menu opens
+    ///
+    /// Called from the message loop when the menu is about to show, e.g. for
+    /// checkmarks that depend on live data. Cheaper than rebuilding the whole
+    /// menu on each open with `with_dynamic_menu`.
+    pub fn on_menu_init<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut MenuInit<T>) + Send + Sync + 'static,
+    {
+        self.on_menu_init = Some(MenuInitHook::new(f));
+        self
+    }
+
+    /// Hook for all messages of the hidden tray icon window
+    ///
+    /// Called with the window handle, message, `WPARAM` and `LPARAM` before the
+    /// message is handled. Returning `Some` skips the default handling and the
+    /// value becomes the result of the message. Useful e.g. for libraries that
+    /// need a window for global hotkeys.
+    ///
+    /// This is an escape hatch, swallowing messages the tray icon relies on
+    /// breaks it.
+    pub fn on_raw_message<F>(mut self, f: F) -> Self
+    where
+        F: Fn(usize, u32, usize, isize) -> Option<isize> + Send + Sync + 'static,
+    {
+        self.on_raw_message = Some(RawMessageHook::new(f));
+        self
+    }
+
     pub fn icon(mut self, icon: Icon) -> Self {
         self.icon = Ok(icon);
         self
     }
 
-    pub fn icon_from_buffer(mut self, buffer: &'static [u8]) -> Self {
+    /// Icon shown while the menu is open
+    ///
+    /// The icon is restored when the menu closes, whether an item was selected
+    /// or the menu was dismissed by clicking away.
+    pub fn active_icon(mut self, icon: Icon) -> Self {
+        self.active_icon = Some(icon);
+        self
+    }
+
+    /// Icon from images of different sizes, see `Icon::from_sizes`
+    pub fn icon_from_sizes<I, B>(mut self, images: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, B)>,
+        B: AsRef<[u8]>,
+    {
+        self.icon = Icon::from_sizes(images);
+        self
+    }
+
+    /// Icon from a buffer of an `.ico` file, see `Icon::from_buffer`
+    pub fn icon_from_buffer<B>(mut self, buffer: B) -> Self
+    where
+        B: AsRef<[u8]>,
+    {
         self.icon = Icon::from_buffer(buffer, None, None);
         self
     }
 
+    /// Build the menu each time it's opened
+    ///
+    /// For menus with e.g. recent items or live data, the function is called
+    /// just before the menu is shown and the previous menu is replaced. Takes
+    /// precedence over `menu` and `TrayIcon::set_menu`.
+    pub fn with_dynamic_menu<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> MenuBuilder<T> + Send + Sync + 'static,
+    {
+        self.dynamic_menu = Some(Callback::new(f));
+        self
+    }
+
     pub fn menu(mut self, menu: MenuBuilder<T>) -> Self
     where
         T: PartialEq + Clone + 'static,
@@ -124,7 +630,57 @@ where
         self
     }
 
+    /// Is any event or menu set, sending them needs a sender or handler
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub(crate) fn has_events(&self) -> bool {
+        [
+            &self.on_click,
+            &self.on_double_click,
+            &self.on_right_click,
+            &self.on_ready,
+            &self.on_high_contrast_changed,
+            &self.on_session_end_query,
+            &self.on_session_end,
+            &self.on_taskbar_moved,
+            &self.on_notification_click,
+            &self.on_notification_timeout,
+            &self.on_notification_hidden,
+            &self.on_hover,
+            &self.on_menu_cancelled,
+            &self.on_menu_closed,
+        ]
+        .iter()
+        .any(|e| e.is_some())
+            || self.menu.is_some()
+            || self.dynamic_menu.is_some()
+            || self.on_unknown_menu_id.is_some()
+            || self.on_click_info.is_some()
+            || self.has_notification_actions()
+    }
+
+    #[cfg(feature = "toast")]
+    fn has_notification_actions(&self) -> bool {
+        !self.notification_actions.is_empty()
+    }
+
+    #[cfg(not(feature = "toast"))]
+    fn has_notification_actions(&self) -> bool {
+        false
+    }
+
     pub fn build(self) -> Result<TrayIcon<T>, Error> {
         Ok(TrayIcon::new(crate::build_trayicon(&self)?, self))
     }
+
+    /// Build the tray icon in its own thread which runs the message loop
+    ///
+    /// Returns a handle for changing the tray icon and the receiver of the
+    /// events, any sender given to the builder is replaced. Dropping the handle
+    /// removes the icon and ends the thread.
+    pub fn build_detached(self) -> Result<(DetachedTrayIcon<T>, Receiver<T>), Error>
+    where
+        T: Send,
+    {
+        DetachedTrayIcon::new(self)
+    }
 }
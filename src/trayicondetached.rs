@@ -0,0 +1,135 @@
+use crate::{Error, Icon, MenuBuilder, TrayIconBuilder};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+enum Command<T>
+where
+    T: PartialEq + Clone + 'static,
+{
+    SetIcon(Icon),
+    SetMenu(MenuBuilder<T>),
+    SetTooltip(String),
+    ShowNotification(String, String),
+    ClearNotification,
+}
+
+/// Tray icon running in its own thread
+///
+/// Created with `TrayIconBuilder::build_detached`. The thread runs the message
+/// loop, so the application doesn't need one. Changes are forwarded to the
+/// thread, dropping this removes the icon and waits for the thread to end.
+pub struct DetachedTrayIcon<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+{
+    commands: Sender<Command<T>>,
+    thread: crate::MessageThread,
+    join: Option<JoinHandle<()>>,
+}
+
+impl<T> DetachedTrayIcon<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+{
+    pub(crate) fn new(
+        builder: TrayIconBuilder<T>,
+    ) -> Result<(DetachedTrayIcon<T>, Receiver<T>), Error> {
+        let (events, events_receiver) = channel();
+        let (commands, commands_receiver) = channel::<Command<T>>();
+        let (result, result_receiver) = sync_channel(1);
+        let builder = builder.sender(events);
+
+        let join = std::thread::spawn(move || {
+            let mut tray_icon = match builder.build() {
+                Ok(tray_icon) => tray_icon,
+                Err(e) => {
+                    let _ = result.send(Err(e));
+                    return;
+                }
+            };
+            let thread = crate::MessageThread::for_window(tray_icon.window_handle());
+            let _ = result.send(Ok(thread));
+            thread.run(|| {
+                for command in commands_receiver.try_iter() {
+                    let _ = match command {
+                        Command::SetIcon(icon) => tray_icon.set_icon(&icon),
+                        Command::SetMenu(menu) => tray_icon.set_menu(&menu),
+                        Command::SetTooltip(tooltip) => tray_icon.set_tooltip(&tooltip),
+                        Command::ShowNotification(title, message) => {
+                            tray_icon.show_notification(&title, &message)
+                        }
+                        Command::ClearNotification => tray_icon.clear_notification(),
+                    };
+                }
+            });
+        });
+
+        let thread = match result_receiver.recv() {
+            Ok(Ok(thread)) => thread,
+            Ok(Err(e)) => {
+                let _ = join.join();
+                return Err(e);
+            }
+            Err(_) => return Err(Error::OsError),
+        };
+
+        Ok((
+            DetachedTrayIcon {
+                commands,
+                thread,
+                join: Some(join),
+            },
+            events_receiver,
+        ))
+    }
+
+    /// Set the icon if changed
+    pub fn set_icon(&self, icon: &Icon) -> Result<(), Error> {
+        self.send(Command::SetIcon(icon.clone()))
+    }
+
+    /// Set the menu if changed
+    pub fn set_menu(&self, menu: &MenuBuilder<T>) -> Result<(), Error> {
+        self.send(Command::SetMenu(menu.clone()))
+    }
+
+    /// Set the tooltip if changed
+    pub fn set_tooltip(&self, tooltip: &str) -> Result<(), Error> {
+        self.send(Command::SetTooltip(tooltip.to_string()))
+    }
+
+    /// Show a balloon notification
+    pub fn show_notification(&self, title: &str, message: &str) -> Result<(), Error> {
+        self.send(Command::ShowNotification(
+            title.to_string(),
+            message.to_string(),
+        ))
+    }
+
+    /// Hide the balloon notification, if it's still showing
+    pub fn clear_notification(&self) -> Result<(), Error> {
+        self.send(Command::ClearNotification)
+    }
+
+    /// Errors only if the thread is gone, failures of the change itself are
+    /// not reported back
+    fn send(&self, command: Command<T>) -> Result<(), Error> {
+        self.commands.send(command).map_err(|_| Error::OsError)?;
+        if !self.thread.wake() {
+            return Err(Error::OsError);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for DetachedTrayIcon<T>
+where
+    T: PartialEq + Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.thread.quit();
+        if let Some(join) = self.join.take() {
+            let _ = join.join();
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 /// Tray Icon event sender
 #[derive(Debug, Clone)]
 pub enum TrayIconSender<T>
@@ -6,6 +8,9 @@ where
 {
     Std(std::sync::mpsc::Sender<T>),
 
+    /// Events are queued and returned from `TrayIcon::pump_messages`
+    Poll(Arc<Mutex<Vec<T>>>),
+
     #[cfg(feature = "winit")]
     Winit(winit::event_loop::EventLoopProxy<T>),
 
@@ -17,11 +22,17 @@ impl<T> TrayIconSender<T>
 where
     T: PartialEq + Clone + 'static,
 {
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
     pub fn send(&self, e: &T) {
         match self {
             TrayIconSender::Std(s) => {
                 let _ = s.send(e.clone());
             }
+            TrayIconSender::Poll(q) => {
+                if let Ok(mut q) = q.lock() {
+                    q.push(e.clone());
+                }
+            }
             #[cfg(feature = "winit")]
             TrayIconSender::Winit(s) => {
                 let _ = s.send_event(e.clone());
@@ -0,0 +1,34 @@
+use crate::Icon;
+
+/// Changes applied at once with `TrayIcon::update`
+///
+/// Only the set properties are changed, the others are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct TrayIconUpdate {
+    pub(crate) icon: Option<Icon>,
+    pub(crate) tooltip: Option<String>,
+    pub(crate) visible: Option<bool>,
+}
+
+impl TrayIconUpdate {
+    pub fn icon(&mut self, icon: &Icon) -> &mut Self {
+        self.icon = Some(icon.clone());
+        self
+    }
+
+    pub fn tooltip(&mut self, tooltip: &str) -> &mut Self {
+        self.tooltip = Some(tooltip.to_string());
+        self
+    }
+
+    /// Show or hide the icon, hidden icon keeps its place in the tray
+    pub fn visible(&mut self, visible: bool) -> &mut Self {
+        self.visible = Some(visible);
+        self
+    }
+
+    /// Is there anything to change
+    pub(crate) fn is_empty(&self) -> bool {
+        self.icon.is_none() && self.tooltip.is_none() && self.visible.is_none()
+    }
+}
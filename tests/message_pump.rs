@@ -0,0 +1,99 @@
+//! End to end tests of the event dispatching through a real message pump
+#![cfg(target_os = "windows")]
+
+use std::sync::mpsc::{channel, TryRecvError};
+use std::time::{Duration, Instant};
+use trayicon::{MenuBuilder, SimulatedInput, TrayIconBuilder};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Events {
+    Ready,
+    Click,
+    DoubleClick,
+    RightClick,
+    Item1,
+    Item2,
+}
+
+/// Create the tray icon on a dedicated thread, simulate the inputs and pump
+/// the messages until the number of events have arrived or it times out
+fn run(inputs: Vec<SimulatedInput<Events>>, count: usize) -> Vec<Events> {
+    let (s, r) = channel();
+    let (stop, stopped) = channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let mut tray_icon = TrayIconBuilder::new()
+            .sender(s)
+            .icon_from_buffer(include_bytes!("../src/testresource/icon1.ico"))
+            .tooltip("Message pump test")
+            .on_ready(Events::Ready)
+            .on_click(Events::Click)
+            .on_double_click(Events::DoubleClick)
+            .on_right_click(Events::RightClick)
+            .auto_show_menu(false)
+            .menu(
+                MenuBuilder::new()
+                    .item("Item 1", Events::Item1)
+                    .item("Item 2", Events::Item2),
+            )
+            .build()
+            .unwrap();
+        for input in &inputs {
+            tray_icon.simulate(input).unwrap();
+        }
+        while let Err(TryRecvError::Empty) = stopped.try_recv() {
+            tray_icon.pump_messages();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut events = vec![];
+    while events.len() < count {
+        match r.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(e) => events.push(e),
+            Err(_) => break,
+        }
+    }
+    drop(stop);
+    thread.join().unwrap();
+    events
+}
+
+#[test]
+fn test_clicks() {
+    let events = run(
+        vec![
+            SimulatedInput::Click,
+            SimulatedInput::DoubleClick,
+            SimulatedInput::RightClick,
+        ],
+        4,
+    );
+    assert_eq!(
+        events,
+        vec![
+            Events::Ready,
+            Events::Click,
+            Events::DoubleClick,
+            Events::RightClick
+        ]
+    );
+}
+
+#[test]
+fn test_menu_selection() {
+    let events = run(
+        vec![
+            SimulatedInput::MenuItem(Events::Item2),
+            SimulatedInput::MenuItem(Events::Item1),
+        ],
+        3,
+    );
+    assert_eq!(events, vec![Events::Ready, Events::Item2, Events::Item1]);
+}
+
+#[test]
+fn test_taskbar_created() {
+    let events = run(vec![SimulatedInput::TaskbarCreated], 2);
+    assert_eq!(events, vec![Events::Ready, Events::Ready]);
+}